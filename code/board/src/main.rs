@@ -0,0 +1,1290 @@
+#![no_std]
+#![no_main]
+
+use defmt_rtt as _;
+
+use stm32f4xx_hal as hal;
+
+use parametric_speaker_core::{
+    battery, berktay, carrier, config, control, current, dead_time, dynamics, eq, fault, feedback,
+    gain, gate, limiter, modulation, pcm1808, pipeline, resample, reset, servo, source, telemetry,
+    thermal, usb_audio,
+};
+
+use core::sync::atomic::{AtomicPtr, Ordering};
+use cortex_m_rt::{exception, ExceptionFrame};
+use defmt::{error, info, warn};
+use fugit::{HertzU32, Rate};
+use heapless::spsc::{self, Consumer, Producer};
+use rtic_monotonics::{systick_monotonic, Monotonic};
+
+use hal::{
+    pac, prelude::*,
+    adc::{
+        config::{AdcConfig, Clock},
+        Adc,
+    },
+    dma::{config::DmaConfig, PeripheralToMemory, Stream3, StreamsTuple, Transfer},
+    gpio::{Input, Output, PinState, PushPull, PB8, PC13},
+    i2s::{
+        stm32_i2s_v12x::{
+            driver::{DataFormat, I2sDriver, I2sDriverConfig},
+            marker::{Master, Philips, Receive},
+        },
+        I2s,
+    },
+    otg_fs::{UsbBus, UsbBusType, USB},
+    pac::{ADC1, DMA1, DMA2, SPI2, TIM1},
+    signature::{VtempCal110, VtempCal30},
+    watchdog::IndependentWatchdog,
+    timer::*,
+};
+
+use usb_device::{bus::UsbBusAllocator, prelude::*};
+
+use battery::{BatteryMonitor, BatteryState};
+use berktay::BerktayEq;
+use carrier::{pwm_frame, OutputStage, PwmFrame, Ramp, FRAME_LEN};
+use config::Config;
+use control::ControlClass;
+use current::{CurrentLimiter, SampleSum};
+use dynamics::Dynamics;
+use eq::Equalizer;
+use fault::{Fault, Faults};
+use gain::{SmoothedGain, UNITY_GAIN};
+use gate::Gate;
+use limiter::Limiter;
+use modulation::{Modulator, PhaseShifter};
+use pipeline::{saturate, Processor};
+use reset::{ResetFlags, ResetReason, ResetRecord};
+use resample::{Resampler, USB_SAMPLE_RATES};
+use servo::{FillServo, Priming};
+use source::{Arbiter, Debouncer, Source, SourceStatus, StreamMonitor};
+use telemetry::Telemetry;
+use thermal::ThermalMonitor;
+use usb_audio::AudioClass;
+
+
+const PWM_FREQ: HertzU32 = Rate::<u32, 1, 1>::kHz(40);
+const AUDIO_QUEUE_SIZE: usize = 4096;
+// Stereo frames per I2S DMA buffer
+const I2S_DMA_FRAMES: usize = 64;
+const I2S_BUFFER_LEN: usize = I2S_DMA_FRAMES * pcm1808::FRAME_LEN;
+// The feedback is read every 8 packets. A host that has not read it for much longer ignores it.
+const FEEDBACK_TIMEOUT_PACKETS: u32 = 64;
+// Hz, at which the battery and the temperatures are measured
+const MONITOR_RATE: u32 = 100;
+// ADC1_IN3 on PA3
+const CURRENT_CHANNEL: u8 = 3;
+// ADC1_IN1 on PA1, ADC1_IN2 on PA2 and the internal temperature sensor
+const BATTERY_CHANNEL: u8 = 1;
+const NTC_CHANNEL: u8 = 2;
+const TEMPERATURE_CHANNEL: u8 = 18;
+// ms. Both interrupt handlers have to have run in every period of the monitor task.
+const WATCHDOG_TIMEOUT: u32 = 100;
+// Timer periods per half of the PWM buffer. Each half is filled while the other one is played.
+const BLOCK_SIZE: usize = 64;
+const _: () = assert!(BLOCK_SIZE >= 32 && BLOCK_SIZE <= 256 && BLOCK_SIZE % 2 == 0);
+
+type PWMType = PwmHz<TIM1, (ChannelBuilder<TIM1, 0, true>, ChannelBuilder<TIM1, 1, true>)>;
+type PwmBuffer = [PwmFrame; 2 * BLOCK_SIZE];
+// Samples of the output current of the last block of timer periods, written in a circle by the DMA
+type CurrentBuffer = [u16; BLOCK_SIZE];
+type I2sRxType = I2sDriver<I2s<SPI2>, Master, Receive, Philips>;
+type I2sTransferType = Transfer<
+    Stream3<DMA1>,
+    0,
+    I2sRxType,
+    PeripheralToMemory,
+    &'static mut [u16; I2S_BUFFER_LEN],
+>;
+
+systick_monotonic!(Mono, 1000);
+
+// Bits of the alive resource, one for every interrupt handler that the watchdog waits for
+const ALIVE_AUDIO: u32 = 1 << 0;
+const ALIVE_OTG_FS: u32 = 1 << 1;
+
+// The reset record for the panic and HardFault handlers, which run outside of RTIC. It is owned by
+// the monitor task, but neither handler returns, so the monitor task never uses it again once they
+// have written to it. Null until init has taken the record.
+static FATAL_RESET_RECORD: AtomicPtr<ResetRecord> = AtomicPtr::new(core::ptr::null_mut());
+
+// The configuration as last changed over USB
+struct SharedConfig {
+    config: Config,
+    // Incremented whenever the configuration changes
+    version: u32,
+}
+
+impl SharedConfig {
+    const fn new() -> Self {
+        Self {
+            config: Config::DEFAULT,
+            version: 0,
+        }
+    }
+
+    // Returns the configuration if it has changed since `version` was last updated
+    fn update(&self, version: &mut u32) -> Option<Config> {
+        if self.version == *version {
+            return None;
+        }
+        *version = self.version;
+        Some(self.config)
+    }
+
+    fn publish(&mut self, config: Config) {
+        self.config = config;
+        self.version = self.version.wrapping_add(1);
+    }
+}
+
+// The state of the USB audio as last seen by the usb task
+#[derive(Clone, Copy)]
+struct UsbStatus {
+    // Incremented for every received audio packet
+    packets: u32,
+    configured: bool,
+    // Q15 gain set by the volume and mute controls of the USB audio class
+    gain: i32,
+}
+
+impl UsbStatus {
+    const fn new() -> Self {
+        Self {
+            packets: 0,
+            configured: false,
+            gain: UNITY_GAIN,
+        }
+    }
+}
+
+fn set_output_state(pwm: &mut PWMType, channel: Channel, enabled: bool) {
+    // The output is disabled when the control signals are complementary
+    // The primary output is ActiveHigh for C1 and ActiveLow for C2
+    pwm.set_complementary_polarity(
+        channel,
+        match channel {
+            Channel::C1 if enabled => Polarity::ActiveLow,
+            Channel::C1 if !enabled => Polarity::ActiveHigh,
+            Channel::C2 if enabled => Polarity::ActiveHigh,
+            Channel::C2 if !enabled => Polarity::ActiveLow,
+            _ => panic!("Only C1 and C2 are supported"),
+        },
+    );
+}
+
+// Sets the DTG bits of the dead-time of both complementary pairs.
+// BDTR also holds MOE, which kill() clears from any priority. The read-modify-write therefore runs
+// with the interrupts masked, so that it writes back MOE as it is and never turns on the outputs
+// again after a break.
+fn set_dead_time(dtg: u8) {
+    // Only the audio task changes the dead-time once the interrupts run
+    cortex_m::interrupt::free(|_| unsafe { (*TIM1::ptr()).bdtr.modify(|_, w| w.dtg().bits(dtg)) });
+}
+
+// Puts the outputs of TIM1 under the control of the main output enable (MOE) bit.
+// While MOE is cleared, every output is driven to its idle level, at which HIN of the 2ED2103 is
+// low and /LIN is high. Both MOSFETs of both legs are then off.
+//
+// The break input pins of TIM1 (PA6 and PB12) are taken by I2S2, so the break is only ever
+// generated in software and BKE stays cleared.
+fn init_break() {
+    // Only called before the interrupts are enabled
+    let tim1 = unsafe { &*TIM1::ptr() };
+    tim1.cr2.modify(|_, w| {
+        w.ois1()
+            .clear_bit()
+            .ois1n()
+            .set_bit()
+            .ois2()
+            .clear_bit()
+            .ois2n()
+            .set_bit()
+    });
+    // Without AOE, MOE stays cleared after a break until the next reset
+    tim1.bdtr.modify(|_, w| {
+        w.ossi()
+            .set_bit()
+            .ossr()
+            .set_bit()
+            .bke()
+            .clear_bit()
+            .aoe()
+            .clear_bit()
+            .moe()
+            .set_bit()
+    });
+}
+
+// Returns the reset record to the panic and HardFault handlers, see FATAL_RESET_RECORD
+fn fatal_reset_record() -> Option<&'static mut ResetRecord> {
+    unsafe { FATAL_RESET_RECORD.load(Ordering::Relaxed).as_mut() }
+}
+
+// Reads and clears the reset flags and the record of the last reset
+fn take_reset_reason(record: &mut ResetRecord) -> ResetReason {
+    // Only called on boot
+    let csr = unsafe { &(*pac::RCC::ptr()).csr };
+    let bits = csr.read();
+    let flags = ResetFlags {
+        power_on: bits.porrstf().bit_is_set(),
+        brownout: bits.borrstf().bit_is_set(),
+        software: bits.sftrstf().bit_is_set(),
+        watchdog: bits.wdgrstf().bit_is_set(),
+    };
+    csr.modify(|_, w| w.rmvf().set_bit());
+    reset::reset_reason(flags, record.take())
+}
+
+// Forces both half-bridges to the safe state through a break event, which clears MOE in hardware.
+// Only a reset turns them back on. Does not take any locks, so any fault source can call it,
+// including the panic handler.
+fn kill() {
+    unsafe { (*TIM1::ptr()).egr.write(|w| w.bg().set_bit()) };
+}
+
+// Logs every fault when it occurs and when it clears
+fn set_fault(faults: &mut Faults, fault: Fault, active: bool) {
+    if faults.set(fault, active) {
+        if active {
+            error!("Fault: {}", fault);
+        } else {
+            info!("Fault cleared: {}", fault);
+        }
+    }
+}
+
+// Samples the output current with a regular conversion of ADC1 at the start of every period of
+// TIM1, and lets the DMA write the samples into a circular buffer without interrupting. The ADC
+// only interrupts when the analog watchdog sees a sample above `trip`, or on an overrun.
+// The DMA keeps writing the buffer, so it has to be static.
+fn init_current_sense(trip: u16, buffer: &CurrentBuffer) {
+    // Only called before the interrupts are enabled
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    let dma2 = unsafe { &*DMA2::ptr() };
+    let tim1 = unsafe { &*TIM1::ptr() };
+    let adc1 = unsafe { &*ADC1::ptr() };
+    rcc.ahb1enr.modify(|_, w| w.dma2en().enabled());
+
+    // The regular conversions can not be triggered by the update of TIM1. Channel 3 has no pin,
+    // and with CCR3 at 1 its reference rises at every update instead.
+    tim1.ccmr2_output().modify(|_, w| w.oc3m().pwm_mode1());
+    tim1.ccr3.write(|w| w.ccr().bits(1));
+
+    // ADC1 is on channel 0 of stream 0
+    let stream = &dma2.st[0];
+    stream.cr.modify(|_, w| w.en().disabled());
+    while stream.cr.read().en().is_enabled() {}
+    dma2.lifcr.write(|w| {
+        w.ctcif0()
+            .set_bit()
+            .chtif0()
+            .set_bit()
+            .cteif0()
+            .set_bit()
+            .cdmeif0()
+            .set_bit()
+            .cfeif0()
+            .set_bit()
+    });
+    stream
+        .par
+        .write(|w| unsafe { w.pa().bits(adc1.dr.as_ptr() as u32) });
+    stream
+        .m0ar
+        .write(|w| unsafe { w.m0a().bits(buffer.as_ptr() as u32) });
+    stream
+        .ndtr
+        .write(|w| unsafe { w.ndt().bits(buffer.len() as u16) });
+    stream.cr.write(|w| unsafe {
+        w.chsel()
+            .bits(0)
+            .pl()
+            .high()
+            .msize()
+            .bits16()
+            .psize()
+            .bits16()
+            .minc()
+            .incremented()
+            .circ()
+            .enabled()
+            .dir()
+            .peripheral_to_memory()
+    });
+    stream.cr.modify(|_, w| w.en().enabled());
+
+    // The current is the only regular channel. The slow channels of the monitor task are taken
+    // as injected conversions, see convert_injected.
+    adc1.sqr1.modify(|_, w| w.l().bits(0));
+    adc1.sqr3
+        .modify(|_, w| unsafe { w.sq1().bits(CURRENT_CHANNEL) });
+    adc1.smpr2
+        .modify(|_, w| w.smp1().cycles480().smp2().cycles480().smp3().cycles56());
+    adc1.smpr1.modify(|_, w| w.smp18().cycles480());
+    set_overcurrent_trip(trip);
+    adc1.cr1.modify(|_, w| unsafe {
+        w.awdch()
+            .bits(CURRENT_CHANNEL)
+            .awdsgl()
+            .set_bit()
+            .awden()
+            .set_bit()
+            .awdie()
+            .set_bit()
+            .ovrie()
+            .set_bit()
+    });
+    adc1.cr2.modify(|_, w| {
+        w.dma()
+            .enabled()
+            .dds()
+            .continuous()
+            .extsel()
+            .tim1cc3()
+            .exten()
+            .rising_edge()
+    });
+}
+
+// Takes a software triggered injected conversion of one of the slow channels of the monitor task.
+// It interrupts the regular conversion of the current, which starts again once it has finished,
+// and the triggers that come in the meantime are held back until then. The current is therefore
+// sampled late for a few periods, but the slow channels always finish.
+fn convert_injected(channel: u8) -> u16 {
+    // Only the monitor task takes injected conversions
+    let adc1 = unsafe { &*ADC1::ptr() };
+    // A single injected conversion is taken from JSQ4
+    adc1.jsqr
+        .write(|w| unsafe { w.jl().bits(0).jsq4().bits(channel) });
+    // The overcurrent trip changes CR2 as well
+    cortex_m::interrupt::free(|_| adc1.cr2.modify(|_, w| w.jswstart().start()));
+    while adc1.sr.read().jeoc().bit_is_clear() {}
+    // Writing 1 has no effect on the flags, so the ones of the overcurrent trip are left alone
+    adc1.sr
+        .write(|w| unsafe { w.bits(!0) }.jeoc().clear_bit().jstrt().clear_bit());
+    adc1.jdr1.read().jdata().bits()
+}
+
+// Sets the ADC sample above which the analog watchdog kills the H-bridge
+fn set_overcurrent_trip(sample: u16) {
+    // Only the audio task changes the threshold once the interrupts run
+    unsafe { (*ADC1::ptr()).htr.write(|w| w.ht().bits(sample)) };
+}
+
+// Returns the rate in Hz at which the audio task fills the blocks of the PWM buffer
+fn block_rate(stage: OutputStage) -> u32 {
+    PWM_FREQ.raw() * stage.periods_per_sample() as u32 / BLOCK_SIZE as u32
+}
+
+// Switches the compare mode of both channels for the output stage
+fn set_output_stage(stage: OutputStage) {
+    // Only the audio task changes the compare mode after the initialisation
+    let tim1 = unsafe { &*TIM1::ptr() };
+    match stage {
+        OutputStage::Duty => tim1
+            .ccmr1_output()
+            .modify(|_, w| w.oc1m().pwm_mode1().oc2m().pwm_mode1()),
+        OutputStage::PhaseShift => {
+            // Toggling keeps the level, so both references are forced low first.
+            // This puts the legs in antiphase when the compare values are the same.
+            tim1.ccmr1_output()
+                .modify(|_, w| w.oc1m().force_inactive().oc2m().force_inactive());
+            tim1.ccmr1_output()
+                .modify(|_, w| w.oc1m().toggle().oc2m().toggle());
+        }
+    }
+}
+
+// Lets TIM1 fetch ARR, RCR, CCR1 and CCR2 from the PWM buffer with a DMA burst on every update.
+// The buffer is played in a circle, and the DMA interrupts once each half has been played.
+// The DMA keeps reading the buffer, so it has to be static.
+fn init_pwm_dma(buffer: &PwmBuffer) {
+    // Only called before the interrupts are enabled
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    let dma2 = unsafe { &*DMA2::ptr() };
+    let tim1 = unsafe { &*TIM1::ptr() };
+    rcc.ahb1enr.modify(|_, w| w.dma2en().enabled());
+
+    // TIM1_UP is on channel 6 of stream 5
+    let stream = &dma2.st[5];
+    stream.cr.modify(|_, w| w.en().disabled());
+    while stream.cr.read().en().is_enabled() {}
+    dma2.hifcr.write(|w| {
+        w.ctcif5()
+            .set_bit()
+            .chtif5()
+            .set_bit()
+            .cteif5()
+            .set_bit()
+            .cdmeif5()
+            .set_bit()
+            .cfeif5()
+            .set_bit()
+    });
+    stream
+        .par
+        .write(|w| unsafe { w.pa().bits(tim1.dmar.as_ptr() as u32) });
+    stream
+        .m0ar
+        .write(|w| unsafe { w.m0a().bits(buffer.as_ptr() as u32) });
+    stream
+        .ndtr
+        .write(|w| unsafe { w.ndt().bits((buffer.len() * FRAME_LEN) as u16) });
+    stream.cr.write(|w| unsafe {
+        w.chsel()
+            .bits(6)
+            .pl()
+            .very_high()
+            .msize()
+            .bits16()
+            .psize()
+            .bits16()
+            .minc()
+            .incremented()
+            .circ()
+            .enabled()
+            .dir()
+            .memory_to_peripheral()
+            .htie()
+            .enabled()
+            .tcie()
+            .enabled()
+    });
+    stream.cr.modify(|_, w| w.en().enabled());
+
+    // The burst starts at ARR (register 11) and is 4 transfers long
+    tim1.dcr
+        .write(|w| unsafe { w.dba().bits(11).dbl().bits(FRAME_LEN as u8 - 1) });
+    tim1.dier.modify(|_, w| w.ude().enabled());
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    kill();
+    if let Some(record) = fatal_reset_record() {
+        record.set(ResetReason::Panic);
+    }
+    error!("{}", defmt::Display2Format(info));
+    // Halts with a HardFault, the same way as panic-probe
+    cortex_m::asm::udf()
+}
+
+#[exception]
+unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+    kill();
+    // A panic ends in a HardFault as well
+    if let Some(record) = fatal_reset_record().filter(|record| !record.is_set()) {
+        record.set(ResetReason::HardFault);
+    }
+    error!("HardFault at PC {:#010x}", frame.pc());
+    loop {}
+}
+
+// The overcurrent trip preempts everything, the audio preempts USB and I2S, and the monitoring and
+// the LED run whenever nothing else does. A lock only masks the tasks that share the resource, so
+// the overcurrent trip is only held off while another task takes the faults.
+#[rtic::app(device = stm32f4xx_hal::pac, peripherals = true, dispatchers = [EXTI0])]
+mod app {
+    use super::*;
+
+    #[shared]
+    struct Shared {
+        config: SharedConfig,
+        battery_state: BatteryState,
+        usb_status: UsbStatus,
+        // Set by the protections to ramp the carrier down and keep it off
+        faults: Faults,
+        // Set by the interrupt handlers whenever they run and cleared by the monitor task, which
+        // only feeds the watchdog when all have run, see ALIVE_AUDIO
+        alive: u32,
+        // Q15 gain by which the carrier is derated when the battery runs low or the bridge runs hot
+        derating_gain: i32,
+        // Measured by the monitor and the audio task. The usb task adds the faults.
+        telemetry: Telemetry,
+    }
+
+    #[local]
+    struct Local {
+        pwm: PWMType,
+        pwm_buffer: &'static mut PwmBuffer,
+        current_buffer: &'static mut CurrentBuffer,
+        phase_shifter: PhaseShifter,
+        // Clock of TIM1 in Hz, which the dead-time is counted in
+        tim1_clock: u32,
+        // mV, measured by ADC1 against the internal reference
+        vdda: u32,
+        // Every source has its own queue. The arbiter in the audio task decides which one is heard.
+        usb_queue_cons: Consumer<'static, i16, AUDIO_QUEUE_SIZE>,
+        analog_queue_cons: Consumer<'static, i16, AUDIO_QUEUE_SIZE>,
+        // Reads high when a plug opens the tip switch of the jack
+        jack_detect: PB8<Input>,
+        usb_device: UsbDevice<'static, UsbBus<USB>>,
+        usb_audio: AudioClass<'static, UsbBus<USB>>,
+        usb_control: ControlClass,
+        usb_queue_prod: Producer<'static, i16, AUDIO_QUEUE_SIZE>,
+        i2s_transfer: I2sTransferType,
+        analog_queue_prod: Producer<'static, i16, AUDIO_QUEUE_SIZE>,
+        adc: Adc<ADC1>,
+        // ADC samples of the internal temperature sensor at 30°C and 110°C
+        temperature_calibration: (u16, u16),
+        watchdog: IndependentWatchdog,
+        // Only written when the firmware gives up and read once on boot
+        reset_record: &'static mut ResetRecord,
+        led: PC13<Output<PushPull>>,
+    }
+
+    #[init(local = [
+        ep_memory: [u32; 1024] = [0; 1024],
+        usb_bus: Option<UsbBusAllocator<UsbBusType>> = None,
+        usb_queue: spsc::Queue<i16, AUDIO_QUEUE_SIZE> = spsc::Queue::new(),
+        analog_queue: spsc::Queue<i16, AUDIO_QUEUE_SIZE> = spsc::Queue::new(),
+        i2s_buffer_0: [u16; I2S_BUFFER_LEN] = [0; I2S_BUFFER_LEN],
+        i2s_buffer_1: [u16; I2S_BUFFER_LEN] = [0; I2S_BUFFER_LEN],
+        pwm_buffer_memory: PwmBuffer = [[0; FRAME_LEN]; 2 * BLOCK_SIZE],
+        current_buffer_memory: CurrentBuffer = [0; BLOCK_SIZE],
+        // Survives the reset, its magic number tells whether it was written
+        #[link_section = ".uninit.RESET_RECORD"]
+        reset_record: ResetRecord = ResetRecord::uninit(),
+    ])]
+    fn init(cx: init::Context) -> (Shared, Local) {
+        let c = cx.core;
+        let p = cx.device;
+
+        let reset_record = cx.local.reset_record;
+        FATAL_RESET_RECORD.store(reset_record, Ordering::Relaxed);
+        let reset_reason = take_reset_reason(reset_record);
+        match reset_reason {
+            ResetReason::PowerOn | ResetReason::Pin => info!("Reset: {}", reset_reason),
+            _ => warn!("Reset: {}", reset_reason),
+        }
+
+        let gpioa = p.GPIOA.split();
+        let gpiob = p.GPIOB.split();
+        let gpioc = p.GPIOC.split();
+
+        let rcc = p.RCC.constrain();
+
+        let clocks = rcc
+            .cfgr
+            .use_hse(25u32.MHz())
+            .sysclk(96.MHz())
+            .i2s_clk(61440.kHz())
+            .require_pll48clk()
+            .freeze();
+
+        Mono::start(c.SYST, clocks.sysclk().raw());
+
+        // The LED is active low
+        let led = gpioc.pc13.into_push_pull_output_in_state(PinState::High);
+
+        let jack_detect = gpiob.pb8.into_pull_up_input();
+
+        let pwm_channels = (
+            Channel1::new(gpioa.pa8).with_complementary(gpiob.pb13),
+            Channel2::new(gpioa.pa9).with_complementary(gpiob.pb14),
+        );
+        let mut pwm = p.TIM1.pwm_hz(pwm_channels, PWM_FREQ, &clocks);
+        init_break();
+
+        // The timer is not prescaled, so the dead-time is counted in timer clock ticks
+        let tim1_clock = clocks.timclk2().raw();
+        let dtg = dead_time::dtg(Config::DEFAULT.dead_time as u32, tim1_clock);
+        set_dead_time(dtg);
+        info!("Dead-time: {}ns", dead_time::dead_time_ns(dtg, tim1_clock));
+
+        // Disable the output on power up
+        set_output_state(&mut pwm, Channel::C1, false);
+        set_output_state(&mut pwm, Channel::C2, false);
+
+        pwm.set_polarity(Channel::C1, Polarity::ActiveHigh);
+        pwm.enable(Channel::C1);
+        pwm.enable_complementary(Channel::C1);
+
+        pwm.set_polarity(Channel::C2, Polarity::ActiveLow);
+        pwm.enable(Channel::C2);
+        pwm.enable_complementary(Channel::C2);
+
+        let max_duty = pwm.get_max_duty();
+        info!("Max duty: {}", max_duty);
+        pwm.set_duty(Channel::C1, 0);
+        pwm.set_duty(Channel::C2, 0);
+        // The buffer starts out idle, the first blocks are filled once the DMA interrupts
+        let pwm_buffer = cx.local.pwm_buffer_memory;
+        pwm_buffer.fill(pwm_frame(max_duty, 0, 0));
+        init_pwm_dma(pwm_buffer);
+
+        // Audio
+        let (usb_queue_prod, usb_queue_cons) = cx.local.usb_queue.split();
+        let (analog_queue_prod, analog_queue_cons) = cx.local.analog_queue.split();
+
+        let usb = USB::new(
+            (p.OTG_FS_GLOBAL, p.OTG_FS_DEVICE, p.OTG_FS_PWRCLK),
+            (gpioa.pa11, gpioa.pa12),
+            &clocks,
+        );
+
+        let usb_bus: &'static _ = cx
+            .local
+            .usb_bus
+            .insert(UsbBusType::new(usb, cx.local.ep_memory));
+
+        let usb_device = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x16c0, 0x27e0))
+            .strings(&[StringDescriptors::default()
+                .manufacturer("Orange_Murker")
+                .product("Parametric Speaker")])
+            .unwrap()
+            .build();
+        let usb_audio = AudioClass::new(usb_bus, &USB_SAMPLE_RATES);
+        let usb_control = ControlClass::new(usb_bus, Config::DEFAULT);
+
+        // Analog input
+        // The PCM1808 is clocked from MCK, so it samples at the same rate as the carrier
+        let i2s = I2s::new(p.SPI2, (gpiob.pb12, gpiob.pb10, gpioa.pa6, gpiob.pb15), &clocks);
+        let mut i2s_rx = I2sDriverConfig::new_master()
+            .receive()
+            .standard(Philips)
+            .data_format(DataFormat::Data24Channel32)
+            .master_clock(true)
+            .request_frequency(PWM_FREQ.raw())
+            .i2s_driver(i2s);
+        info!("I2S sample rate: {}", i2s_rx.sample_rate());
+        i2s_rx.set_rx_dma(true);
+
+        let dma1 = StreamsTuple::new(p.DMA1);
+        let mut i2s_transfer = Transfer::init_peripheral_to_memory(
+            dma1.3,
+            i2s_rx,
+            cx.local.i2s_buffer_0,
+            Some(cx.local.i2s_buffer_1),
+            DmaConfig::default()
+                .memory_increment(true)
+                .double_buffer(true)
+                .transfer_complete_interrupt(true),
+        );
+        i2s_transfer.start(|i2s_rx| i2s_rx.enable());
+
+        // The current is measured with regular conversions triggered by TIM1. The battery and the
+        // temperatures are measured with injected conversions in the monitor task.
+        let _battery_pin = gpioa.pa1.into_analog();
+        let _ntc_pin = gpioa.pa2.into_analog();
+        let _current_pin = gpioa.pa3.into_analog();
+        let mut adc = Adc::adc1(
+            p.ADC1,
+            true,
+            AdcConfig::default().clock(Clock::Pclk2_div_4),
+        );
+        adc.enable_temperature_and_vref();
+        let vdda = adc.reference_voltage();
+        info!("VDDA: {}mV", vdda);
+        let current_buffer = cx.local.current_buffer_memory;
+        init_current_sense(
+            current::sample(Config::DEFAULT.overcurrent_trip, vdda),
+            current_buffer,
+        );
+        // The conversions are triggered, so the ADC stays on
+        adc.enable();
+
+        // Started by the monitor task, which feeds it
+        let mut watchdog = IndependentWatchdog::new(p.IWDG);
+        watchdog.stop_on_debug(&p.DBGMCU, true);
+
+        monitor::spawn().unwrap();
+        ui::spawn().unwrap();
+
+        (
+            Shared {
+                config: SharedConfig::new(),
+                battery_state: BatteryState::Normal,
+                usb_status: UsbStatus::new(),
+                faults: Faults::new(),
+                alive: 0,
+                derating_gain: UNITY_GAIN,
+                telemetry: Telemetry {
+                    ntc_temperature: i32::MIN,
+                    reset_reason: reset_reason as i32,
+                    ..Telemetry::default()
+                },
+            },
+            Local {
+                pwm,
+                pwm_buffer,
+                current_buffer,
+                phase_shifter: PhaseShifter::new(max_duty as u32),
+                tim1_clock,
+                vdda,
+                usb_queue_cons,
+                analog_queue_cons,
+                jack_detect,
+                usb_device,
+                usb_audio,
+                usb_control,
+                usb_queue_prod,
+                i2s_transfer,
+                analog_queue_prod,
+                adc,
+                temperature_calibration: (VtempCal30::get().read(), VtempCal110::get().read()),
+                watchdog,
+                reset_record,
+                led,
+            },
+        )
+    }
+
+    // Measures the battery and the temperatures, and feeds the watchdog while the interrupt
+    // handlers keep running
+    #[task(
+        priority = 1,
+        shared = [config, battery_state, faults, alive, derating_gain, telemetry],
+        local = [adc, temperature_calibration, watchdog, reset_record]
+    )]
+    async fn monitor(mut cx: monitor::Context) {
+        let monitor::LocalResources {
+            adc,
+            temperature_calibration,
+            watchdog,
+            reset_record,
+            ..
+        } = cx.local;
+        let (cal30, cal110) = *temperature_calibration;
+        let mut battery = BatteryMonitor::new();
+        let mut battery_state = BatteryState::Normal;
+        let mut thermal = ThermalMonitor::new();
+        let mut overheated = false;
+        let mut ntc_fitted = true;
+        let mut config_seen = u32::MAX;
+
+        watchdog.start(WATCHDOG_TIMEOUT.millis());
+        let mut stalled = false;
+        // The USB task only runs on USB events, so it is pended here to see that it still runs
+        rtic::pend(pac::Interrupt::OTG_FS);
+        // The audio task first runs one block after the PWM DMA has started, which may well be
+        // after init has returned. Every handler gets a whole period to run before it is checked.
+        Mono::delay((1000 / MONITOR_RATE).millis()).await;
+
+        loop {
+            // A handler that never returns starves this task, one that stopped running is caught
+            // here. Either way the watchdog resets.
+            let alive = cx.shared.alive.lock(core::mem::take);
+            if alive == ALIVE_AUDIO | ALIVE_OTG_FS {
+                watchdog.feed();
+            } else if !stalled {
+                let reason = if alive & ALIVE_AUDIO == 0 {
+                    ResetReason::AudioStalled
+                } else {
+                    ResetReason::UsbStalled
+                };
+                kill();
+                cx.shared
+                    .faults
+                    .lock(|faults| set_fault(faults, Fault::Stall, true));
+                reset_record.set(reason);
+                error!("{}, waiting for the watchdog", reason);
+                stalled = true;
+            }
+
+            let changed = cx.shared.config.lock(|config| config.update(&mut config_seen));
+            if let Some(config) = changed {
+                info!("Battery: {}", config.battery_chemistry);
+                battery.set_chemistry(config.battery_chemistry, MONITOR_RATE);
+                thermal.set_limits(config.thermal_derate, config.thermal_trip, MONITOR_RATE);
+            }
+
+            let sample = convert_injected(BATTERY_CHANNEL);
+            let state =
+                battery.update(battery::battery_voltage(adc.sample_to_millivolts(sample) as u32));
+            if state != battery_state {
+                info!("Battery {} at {}mV", state, battery.voltage());
+                battery_state = state;
+                cx.shared.battery_state.lock(|shared| *shared = state);
+            }
+            cx.shared.faults.lock(|faults| {
+                set_fault(faults, Fault::Undervoltage, state == BatteryState::Cutoff)
+            });
+
+            let sample = convert_injected(TEMPERATURE_CHANNEL);
+            let internal = thermal::internal_temperature(
+                adc.sample_to_millivolts(sample) as u32,
+                cal30,
+                cal110,
+            );
+            let internal = (internal * 10.0) as i32;
+            // Without an NTC only the internal sensor is used
+            let ntc = thermal::ntc_temperature(convert_injected(NTC_CHANNEL))
+                .map(|temperature| (temperature * 10.0) as i32);
+            if ntc.is_some() != ntc_fitted {
+                ntc_fitted = ntc.is_some();
+                if !ntc_fitted {
+                    warn!("NTC open or shorted");
+                }
+            }
+            let tripped = thermal.update(ntc.map_or(internal, |ntc| ntc.max(internal)));
+            if tripped != overheated {
+                info!("Temperature: {}°C", thermal.temperature() / 10);
+                overheated = tripped;
+            }
+            cx.shared
+                .faults
+                .lock(|faults| set_fault(faults, Fault::Overtemperature, tripped));
+
+            cx.shared
+                .derating_gain
+                .lock(|gain| *gain = battery.gain().min(thermal.gain()));
+            cx.shared.telemetry.lock(|telemetry| {
+                telemetry.battery_voltage = battery.voltage() as i32;
+                telemetry.state_of_charge = battery.state_of_charge() as i32;
+                telemetry.ntc_temperature = ntc.unwrap_or(i32::MIN);
+                telemetry.internal_temperature = internal;
+            });
+
+            rtic::pend(pac::Interrupt::OTG_FS);
+            Mono::delay((1000 / MONITOR_RATE).millis()).await;
+        }
+    }
+
+    // Blinks the LED slowly on a low battery and fast after the cutoff
+    #[task(priority = 1, shared = [battery_state], local = [led])]
+    async fn ui(mut cx: ui::Context) {
+        let mut led_on = false;
+        loop {
+            let period: u32 = match cx.shared.battery_state.lock(|state| *state) {
+                BatteryState::Normal => {
+                    led_on = false;
+                    100
+                }
+                BatteryState::Low => {
+                    led_on = !led_on;
+                    500
+                }
+                BatteryState::Cutoff => {
+                    led_on = !led_on;
+                    125
+                }
+            };
+            // The LED is active low
+            cx.local.led.set_state((!led_on).into());
+            Mono::delay(period.millis()).await;
+        }
+    }
+
+    // Kills the H-bridge when the analog watchdog sees a sample of the current above the
+    // overcurrent trip
+    #[task(binds = ADC, priority = 4, shared = [faults])]
+    fn current_sense(mut cx: current_sense::Context) {
+        let adc1 = unsafe { &*ADC1::ptr() };
+        let status = adc1.sr.read();
+        // Writing 1 has no effect on the flags, so the ones of the monitor task are left alone
+        if status.awd().bit_is_set() {
+            adc1.sr.write(|w| unsafe { w.bits(!0) }.awd().clear_bit());
+            // The H-bridge stays off until the next reset, so there is nothing more to catch
+            adc1.cr1.modify(|_, w| w.awdie().clear_bit());
+            kill();
+            cx.shared
+                .faults
+                .lock(|faults| set_fault(faults, Fault::Overcurrent, true));
+        }
+        // After an overrun the ADC stops requesting the DMA until it is enabled again
+        if status.ovr().bit_is_set() {
+            adc1.cr2.modify(|_, w| w.dma().disabled());
+            adc1.sr.write(|w| unsafe { w.bits(!0) }.ovr().clear_bit());
+            adc1.cr2.modify(|_, w| w.dma().enabled());
+            warn!("Current sense overrun");
+        }
+    }
+
+    // Fills the half of the PWM buffer that the DMA has just finished with the next block
+    #[task(
+        binds = DMA2_STREAM5,
+        priority = 3,
+        shared = [
+            config,
+            usb_status,
+            faults,
+            alive,
+            derating_gain,
+            telemetry,
+        ],
+        local = [
+            pwm,
+            pwm_buffer,
+            current_buffer,
+            phase_shifter,
+            tim1_clock,
+            vdda,
+            usb_queue_cons,
+            analog_queue_cons,
+            jack_detect,
+            config_seen: u32 = u32::MAX,
+            arbiter: Arbiter = Arbiter::new(Config::DEFAULT.source_mode),
+            usb_monitor: StreamMonitor = StreamMonitor::new(),
+            jack_debouncer: Debouncer = Debouncer::new(false),
+            usb_priming: Priming = Priming::new(AUDIO_QUEUE_SIZE / 2),
+            analog_priming: Priming = Priming::new(AUDIO_QUEUE_SIZE / 2),
+            usb_gate: Gate = Gate::new(),
+            analog_gate: Gate = Gate::new(),
+            usb_dynamics: Dynamics = Dynamics::new(),
+            analog_dynamics: Dynamics = Dynamics::new(),
+            usb_gain_stage: SmoothedGain = SmoothedGain::new(UNITY_GAIN),
+            equalizer: Equalizer = Equalizer::new(),
+            berktay_eq: BerktayEq = BerktayEq::new(),
+            limiter: Limiter = Limiter::new(),
+            modulator: Modulator = Modulator::new(Config::DEFAULT.modulation, UNITY_GAIN),
+            ramp: Ramp = Ramp::new(),
+            current_limiter: CurrentLimiter = CurrentLimiter::new(),
+            // Samples
+            fault_ramp: u32 = 1,
+            // The output is disabled on power up
+            output_enabled: bool = false,
+            output_stage: OutputStage = OutputStage::Duty,
+            // Stage of the blocks that are being filled
+            next_stage: OutputStage = OutputStage::Duty,
+            // Whether the block that was filled last needs the output enabled
+            next_enabled: bool = false,
+        ]
+    )]
+    fn audio(mut cx: audio::Context) {
+        let audio::LocalResources {
+            pwm,
+            pwm_buffer,
+            current_buffer,
+            phase_shifter,
+            tim1_clock,
+            vdda,
+            usb_queue_cons: usb_queue,
+            analog_queue_cons: analog_queue,
+            jack_detect,
+            config_seen,
+            arbiter,
+            usb_monitor,
+            jack_debouncer,
+            usb_priming,
+            analog_priming,
+            usb_gate,
+            analog_gate,
+            usb_dynamics,
+            analog_dynamics,
+            usb_gain_stage,
+            equalizer,
+            berktay_eq,
+            limiter,
+            modulator,
+            ramp,
+            current_limiter,
+            fault_ramp,
+            output_enabled,
+            output_stage,
+            next_stage,
+            next_enabled,
+            ..
+        } = cx.local;
+
+        // The half that is not being played is filled, whichever flag is set
+        let dma2 = unsafe { &*DMA2::ptr() };
+        let flags = dma2.hisr.read();
+        if flags.htif5().bit_is_set() && flags.tcif5().bit_is_set() {
+            error!("PWM buffer underrun");
+        }
+        dma2.hifcr.write(|w| w.chtif5().set_bit().ctcif5().set_bit());
+        let remaining = dma2.st[5].ndtr.read().ndt().bits() as usize;
+        let half = if remaining > BLOCK_SIZE * FRAME_LEN { 1 } else { 0 };
+        cx.shared.alive.lock(|alive| *alive |= ALIVE_AUDIO);
+
+        // The block that was filled last has just started playing, so its output stage and state
+        // take effect now
+        if *next_stage != *output_stage {
+            info!("Output stage: {}", *next_stage);
+            set_output_stage(*next_stage);
+            *output_stage = *next_stage;
+        }
+        // The H-bridge is only switched off once a whole block has ramped out
+        if *next_enabled != *output_enabled {
+            set_output_state(pwm, Channel::C1, *next_enabled);
+            set_output_state(pwm, Channel::C2, *next_enabled);
+            *output_enabled = *next_enabled;
+        }
+
+        if let Some(config) = cx.shared.config.lock(|config| config.update(config_seen)) {
+            arbiter.set_mode(config.source_mode);
+            set_dead_time(dead_time::dtg(config.dead_time as u32, *tim1_clock));
+            *next_stage = config.output_stage;
+            usb_gate.set_settings(&config.gates[Source::Usb as usize], PWM_FREQ.raw());
+            analog_gate.set_settings(&config.gates[Source::Analog as usize], PWM_FREQ.raw());
+            usb_dynamics.set_preset(config.usb_dynamics, PWM_FREQ.raw());
+            analog_dynamics.set_preset(config.analog_dynamics, PWM_FREQ.raw());
+            equalizer.set_bands(&config.eq, PWM_FREQ.raw());
+            berktay_eq.set_corners(
+                config.berktay_corner as u32,
+                config.berktay_high_pass as u32,
+                PWM_FREQ.raw(),
+            );
+            *fault_ramp = config.fault_ramp as u32 * (PWM_FREQ.raw() / 1000) / 1000;
+            current_limiter.set_limit(config.current_limit, block_rate(config.output_stage));
+            set_overcurrent_trip(current::sample(config.overcurrent_trip, *vdda));
+            limiter.set_parameters(
+                config.limiter_threshold,
+                config.limiter_attack as u32,
+                config.limiter_release as u32,
+                PWM_FREQ.raw(),
+            );
+            modulator.set_modulation(config.modulation);
+            modulator.set_index(modulation::index_from_percent(config.modulation_index));
+        }
+
+        // The mean current of the last block of periods
+        let mut samples = SampleSum::new();
+        for sample in current_buffer.iter() {
+            // Written by the DMA
+            samples.add(unsafe { core::ptr::read_volatile(sample) });
+        }
+        let current = current::current(samples.take(), *vdda);
+        let current_gain = current_limiter.process(current);
+        let derating_gain = cx.shared.derating_gain.lock(|gain| *gain);
+        let faulted = cx.shared.faults.lock(|faults| faults.any());
+        let usb = cx.shared.usb_status.lock(|status| *status);
+        usb_gain_stage.set_target(usb.gain);
+
+        let periods_per_sample = next_stage.periods_per_sample();
+        let len = BLOCK_SIZE / periods_per_sample;
+        let mut usb_block = [0; BLOCK_SIZE];
+        let mut analog_block = [0; BLOCK_SIZE];
+        let usb_block = &mut usb_block[..len];
+        let analog_block = &mut analog_block[..len];
+        for (usb_value, analog_value) in usb_block.iter_mut().zip(analog_block.iter_mut()) {
+            let status = SourceStatus {
+                usb_configured: usb.configured,
+                usb_streaming: usb_monitor.update(usb.packets),
+                jack_plugged: jack_debouncer.update(jack_detect.is_high()),
+            };
+            if arbiter.update(status) {
+                info!("Audio source: {}", arbiter.active());
+            }
+
+            // Both queues are always drained so that the inactive source does not overrun
+            let next_sample = |queue: &mut Consumer<'static, i16, AUDIO_QUEUE_SIZE>,
+                               priming: &mut Priming,
+                               source| {
+                if !priming.is_primed(queue.len()) {
+                    return 0;
+                }
+                queue.dequeue().unwrap_or_else(|| {
+                    priming.underrun();
+                    if arbiter.is_audible(source) {
+                        error!("Underrun");
+                    }
+                    0
+                })
+            };
+            *usb_value = next_sample(usb_queue, usb_priming, Source::Usb) as i32;
+            *analog_value = next_sample(analog_queue, analog_priming, Source::Analog) as i32;
+        }
+
+        // Every source keeps its own dynamics state, which is only advanced while it is heard.
+        // The gates follow the sources before their dynamics.
+        let usb_audible = arbiter.is_audible(Source::Usb);
+        let analog_audible = arbiter.is_audible(Source::Analog);
+        (&mut *usb_gate)
+            .then(usb_audible.then_some(&mut *usb_dynamics))
+            .then(&mut *usb_gain_stage)
+            .process(usb_block);
+        (&mut *analog_gate)
+            .then(analog_audible.then_some(&mut *analog_dynamics))
+            .process(analog_block);
+
+        // The carrier ramps in and out with the gates of the sources that are heard.
+        // A fault ramps it out in the fault ramp time and keeps it off.
+        let mut open = false;
+        let mut fade = 1;
+        if usb_audible {
+            open |= usb_gate.is_open();
+            fade = fade.max(usb_gate.fade());
+        }
+        if analog_audible {
+            open |= analog_gate.is_open();
+            fade = fade.max(analog_gate.fade());
+        }
+
+        let mut samples = [0; BLOCK_SIZE];
+        let mut levels = [0; BLOCK_SIZE];
+        let samples = &mut samples[..len];
+        let mut block_enabled = false;
+        for (((sample, level_out), &usb_value), &analog_value) in samples
+            .iter_mut()
+            .zip(levels.iter_mut())
+            .zip(usb_block.iter())
+            .zip(analog_block.iter())
+        {
+            *sample = arbiter.mix(saturate(usb_value), saturate(analog_value)) as i32;
+            let level = if faulted {
+                ramp.update(false, *fault_ramp)
+            } else {
+                ramp.update(open, fade)
+            };
+            block_enabled |= !ramp.is_off();
+            *level_out = carrier::level(level, &[derating_gain, current_gain]);
+        }
+
+        let mut pipeline = (&mut *equalizer)
+            .then(&mut *berktay_eq)
+            .then(&mut *limiter);
+        pipeline.process(samples);
+
+        let block = &mut pwm_buffer[half * BLOCK_SIZE..][..BLOCK_SIZE];
+        for ((frames, &value), &level) in block
+            .chunks_exact_mut(periods_per_sample)
+            .zip(samples.iter())
+            .zip(levels.iter())
+        {
+            let envelope = modulator.modulate(value as i16);
+            let period = phase_shifter.update(envelope.phase);
+            let frame = carrier::frame(*next_stage, period as u16, envelope.amplitude, level);
+            frames.fill(frame);
+        }
+        *next_enabled = block_enabled;
+        cx.shared.telemetry.lock(|telemetry| {
+            telemetry.gain_reduction = -limiter::gain_to_db(limiter.gain());
+            telemetry.current = current_limiter.average();
+        });
+    }
+
+    #[task(
+        binds = OTG_FS,
+        priority = 2,
+        shared = [config, usb_status, faults, alive, telemetry],
+        local = [
+            usb_device,
+            usb_audio,
+            usb_control,
+            usb_queue_prod,
+            sample_rate: u32 = 0,
+            resampler: Resampler = Resampler::new(48000, PWM_FREQ.raw()),
+            servo: FillServo = FillServo::new(AUDIO_QUEUE_SIZE / 2),
+            packets_since_feedback: u32 = FEEDBACK_TIMEOUT_PACKETS,
+        ]
+    )]
+    fn usb(mut cx: usb::Context) {
+        let usb::LocalResources {
+            usb_device: usb_dev,
+            usb_audio,
+            usb_control,
+            usb_queue_prod: queue,
+            sample_rate,
+            resampler,
+            servo,
+            packets_since_feedback,
+            ..
+        } = cx.local;
+
+        cx.shared.alive.lock(|alive| *alive |= ALIVE_OTG_FS);
+
+        if usb_dev.poll(&mut [usb_audio, usb_control]) {
+            let mut buf: [u8; 1024] = [0u8; 1024];
+            if let Ok(len) = usb_audio.read(&mut buf) {
+                cx.shared
+                    .usb_status
+                    .lock(|status| status.packets = status.packets.wrapping_add(1));
+                let data = &buf[0..len];
+                let rate = usb_audio.sample_rate();
+                if rate != *sample_rate {
+                    *sample_rate = rate;
+                    resampler.set_rates(rate, PWM_FREQ.raw());
+                }
+                for x in data.chunks_exact(2) {
+                    let val = i16::from_le_bytes(
+                        x.try_into()
+                            .expect("Should not panic because chunks are always 2 bytes"),
+                    );
+                    resampler.process(val, |val| {
+                        if queue.enqueue(val).is_err() {
+                            error!("Overrun");
+                        }
+                    });
+                }
+
+                // Keep the queue half full by making the host follow the carrier clock.
+                // If the host ignores the feedback, the resampler follows the host clock instead.
+                if usb_audio.take_feedback_read() {
+                    *packets_since_feedback = 0;
+                } else {
+                    *packets_since_feedback = packets_since_feedback.saturating_add(1);
+                }
+                let correction = servo.update(queue.len());
+                if *packets_since_feedback < FEEDBACK_TIMEOUT_PACKETS {
+                    usb_audio.set_feedback(feedback::feedback(rate, correction));
+                    resampler.set_correction(0);
+                } else {
+                    usb_audio.set_feedback(feedback::nominal_feedback(rate));
+                    resampler.set_correction(correction);
+                }
+            }
+
+            if let Some(config) = usb_control.take_changed() {
+                info!("Configuration changed");
+                cx.shared.config.lock(|shared| shared.publish(config));
+            }
+        }
+
+        let configured = usb_dev.state() == UsbDeviceState::Configured;
+        let (volume, mute) = usb_audio.volume();
+        cx.shared.usb_status.lock(|status| {
+            status.configured = configured;
+            status.gain = gain::volume_to_gain(volume, mute);
+        });
+
+        let faults = cx.shared.faults.lock(|faults| *faults);
+        let mut telemetry = cx.shared.telemetry.lock(|telemetry| *telemetry);
+        telemetry.fault_count = faults.count() as i32;
+        telemetry.faults = faults.bits() as i32;
+        usb_control.set_telemetry(telemetry);
+    }
+
+    #[task(
+        binds = DMA1_STREAM3,
+        priority = 2,
+        local = [
+            i2s_transfer,
+            analog_queue_prod,
+            // The PCM1808 nominally runs at the carrier rate, but it cannot be rate controlled
+            resampler: Resampler = Resampler::new(PWM_FREQ.raw(), PWM_FREQ.raw()),
+            servo: FillServo = FillServo::new(AUDIO_QUEUE_SIZE / 2),
+        ]
+    )]
+    fn i2s(cx: i2s::Context) {
+        let i2s::LocalResources {
+            i2s_transfer: transfer,
+            analog_queue_prod: queue,
+            resampler,
+            servo,
+            ..
+        } = cx.local;
+
+        if !transfer.is_transfer_complete() {
+            return;
+        }
+        transfer.clear_transfer_complete();
+
+        // In double buffer mode the closure gets the buffer that the DMA has just filled
+        let result = transfer.next_transfer_with(|buf, _current| {
+            for frame in buf.chunks_exact(pcm1808::FRAME_LEN) {
+                let val = pcm1808::frame_to_i16(
+                    frame
+                        .try_into()
+                        .expect("Should not panic because chunks are always a full frame"),
+                );
+                resampler.process(val, |val| {
+                    if queue.enqueue(val).is_err() {
+                        error!("Overrun");
+                    }
+                });
+            }
+            (buf, ())
+        });
+        resampler.set_correction(servo.update(queue.len()));
+
+        if result.is_err() {
+            error!("I2S DMA error");
+        }
+    }
+}
@@ -0,0 +1,210 @@
+// Monitoring of the battery that powers the H-bridge
+//
+// The battery voltage is low-passed, so that short sags under load do not trip the cutoff.
+// Below the warning threshold the output is derated and the LED signals a low battery.
+// Below the cutoff threshold the carrier is switched off. Every state is only left once the
+// voltage has recovered by the hysteresis.
+
+use crate::filter::{self, OnePole};
+use crate::gain::UNITY_GAIN;
+
+// The battery is measured through a 100k/10k divider, so that 4S packs and charging lead-acid
+// batteries stay well within the ADC range
+pub const DIVIDER_TOP: u32 = 100_000;
+pub const DIVIDER_BOTTOM: u32 = 10_000;
+
+// µs
+const FILTER_TIME: u32 = 500_000;
+// Q15 gain at the cutoff threshold
+const MIN_GAIN: i32 = UNITY_GAIN / 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Chemistry {
+    // Measured and reported, but never cut off or derated
+    Off,
+    LiIon2S,
+    LiIon3S,
+    LiIon4S,
+    // 12V
+    LeadAcid,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum BatteryState {
+    Normal,
+    Low,
+    Cutoff,
+}
+
+struct Settings {
+    cells: u32,
+    // mV per cell
+    cutoff: u32,
+    warning: u32,
+    hysteresis: u32,
+    // Ascending mV per cell and state of charge in percent, interpolated linearly
+    charge: &'static [(u32, u32)],
+}
+
+const LI_ION_CHARGE: [(u32, u32); 9] = [
+    (3000, 0),
+    (3300, 3),
+    (3500, 8),
+    (3600, 15),
+    (3700, 30),
+    (3800, 50),
+    (3900, 65),
+    (4000, 78),
+    (4200, 100),
+];
+
+const fn li_ion(cells: u32) -> Settings {
+    Settings {
+        cells,
+        cutoff: 3200,
+        warning: 3500,
+        hysteresis: 150,
+        charge: &LI_ION_CHARGE,
+    }
+}
+
+const LI_ION_2S: Settings = li_ion(2);
+const LI_ION_3S: Settings = li_ion(3);
+const LI_ION_4S: Settings = li_ion(4);
+
+const LEAD_ACID_CHARGE: [(u32, u32); 6] = [
+    (1930, 0),
+    (1967, 10),
+    (2000, 25),
+    (2033, 50),
+    (2067, 75),
+    (2117, 100),
+];
+
+const LEAD_ACID: Settings = Settings {
+    cells: 6,
+    cutoff: 1800,
+    warning: 1950,
+    hysteresis: 50,
+    charge: &LEAD_ACID_CHARGE,
+};
+
+impl Chemistry {
+    fn settings(self) -> Option<&'static Settings> {
+        match self {
+            Chemistry::Off => None,
+            Chemistry::LiIon2S => Some(&LI_ION_2S),
+            Chemistry::LiIon3S => Some(&LI_ION_3S),
+            Chemistry::LiIon4S => Some(&LI_ION_4S),
+            Chemistry::LeadAcid => Some(&LEAD_ACID),
+        }
+    }
+}
+
+// Converts the voltage at the ADC pin to the battery voltage, both in mV
+pub fn battery_voltage(pin: u32) -> u32 {
+    pin * (DIVIDER_TOP + DIVIDER_BOTTOM) / DIVIDER_BOTTOM
+}
+
+// Returns the state of charge in percent for a voltage per cell in mV
+fn state_of_charge(cell: u32, charge: &[(u32, u32)]) -> u32 {
+    let (first_voltage, first_charge) = charge[0];
+    if cell <= first_voltage {
+        return first_charge;
+    }
+    for window in charge.windows(2) {
+        let ((v0, c0), (v1, c1)) = (window[0], window[1]);
+        if cell <= v1 {
+            return c0 + (c1 - c0) * (cell - v0) / (v1 - v0);
+        }
+    }
+    charge[charge.len() - 1].1
+}
+
+pub struct BatteryMonitor {
+    settings: Option<&'static Settings>,
+    // µV, for precision at the long time constant
+    voltage: OnePole,
+    primed: bool,
+    state: BatteryState,
+}
+
+impl BatteryMonitor {
+    pub const fn new() -> Self {
+        Self {
+            settings: None,
+            voltage: OnePole::new(),
+            primed: false,
+            state: BatteryState::Normal,
+        }
+    }
+
+    // `rate` is the rate in Hz at which `update` is called
+    pub fn set_chemistry(&mut self, chemistry: Chemistry, rate: u32) {
+        self.settings = chemistry.settings();
+        self.voltage
+            .set_coefficient(filter::time_constant(FILTER_TIME, rate));
+        if self.settings.is_none() {
+            self.state = BatteryState::Normal;
+        }
+    }
+
+    // Takes a battery voltage in mV and returns the new state
+    pub fn update(&mut self, voltage: u32) -> BatteryState {
+        // The filter starts at the first measurement, not at 0V
+        if !self.primed {
+            self.voltage.reset(voltage as i32 * 1000);
+            self.primed = true;
+        }
+        self.voltage.process(voltage as i32 * 1000);
+
+        let Some(settings) = self.settings else {
+            return self.state;
+        };
+        let cell = self.voltage() / settings.cells;
+        self.state = match self.state {
+            _ if cell < settings.cutoff => BatteryState::Cutoff,
+            BatteryState::Cutoff if cell < settings.cutoff + settings.hysteresis => {
+                BatteryState::Cutoff
+            }
+            _ if cell < settings.warning => BatteryState::Low,
+            BatteryState::Low | BatteryState::Cutoff
+                if cell < settings.warning + settings.hysteresis =>
+            {
+                BatteryState::Low
+            }
+            _ => BatteryState::Normal,
+        };
+        self.state
+    }
+
+    // Filtered battery voltage in mV
+    pub fn voltage(&self) -> u32 {
+        self.voltage.output() as u32 / 1000
+    }
+
+    // Percent, 0 without a chemistry
+    pub fn state_of_charge(&self) -> u32 {
+        match self.settings {
+            Some(settings) => state_of_charge(self.voltage() / settings.cells, settings.charge),
+            None => 0,
+        }
+    }
+
+    // Q15 gain that falls linearly from unity at the warning threshold to MIN_GAIN at the cutoff
+    pub fn gain(&self) -> i32 {
+        let Some(settings) = self.settings else {
+            return UNITY_GAIN;
+        };
+        let cell = (self.voltage() / settings.cells).clamp(settings.cutoff, settings.warning);
+        MIN_GAIN
+            + ((UNITY_GAIN - MIN_GAIN) as u32 * (cell - settings.cutoff)
+                / (settings.warning - settings.cutoff)) as i32
+    }
+}
+
+impl Default for BatteryMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
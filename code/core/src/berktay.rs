@@ -0,0 +1,53 @@
+// Pre-equalization for the self-demodulation of the parametric array
+//
+// By Berktay's far-field solution the audible pressure is proportional to the second derivative
+// of the squared envelope. That is why bass is weak and treble is harsh. Integrating the audio
+// twice before the square root of SRAM makes the demodulated audio flat above the corner
+// frequency. The integrators are leaky and preceded by a high-pass, so they cannot run away.
+
+use crate::filter::{self, OnePole};
+
+pub struct BerktayEq {
+    enabled: bool,
+    high_pass: OnePole,
+    integrators: [OnePole; 2],
+}
+
+impl BerktayEq {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            high_pass: OnePole::new(),
+            integrators: [OnePole::new(), OnePole::new()],
+        }
+    }
+
+    // A corner frequency of 0 disables the equalization
+    pub fn set_corners(&mut self, corner: u32, high_pass: u32, sample_rate: u32) {
+        self.enabled = corner != 0;
+        self.high_pass
+            .set_coefficient(filter::corner(high_pass, sample_rate));
+        for integrator in &mut self.integrators {
+            integrator.set_coefficient(filter::corner(corner, sample_rate));
+        }
+    }
+
+    pub fn process(&mut self, x: i16) -> i16 {
+        if !self.enabled {
+            return x;
+        }
+
+        // Extra precision for the integrators
+        let x = (x as i32) << 14;
+        let y = x - self.high_pass.process(x);
+        let y = self.integrators[0].process(y);
+        let y = self.integrators[1].process(y);
+        (y >> 14).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+    }
+}
+
+impl Default for BerktayEq {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,139 @@
+// Output stage of the H-bridge and soft start and stop of the carrier
+//
+// Switching the H-bridge on or off at full amplitude is a hard 40kHz step at the transducers.
+// That pops and causes current spikes. Instead, the amplitude is ramped up after the bridge has
+// been switched on, and down before it is switched off.
+
+use crate::gain::UNITY_GAIN;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum OutputStage {
+    // Both legs get the same duty, which sets the amplitude
+    Duty,
+    // Every leg is a 50% square wave and the phase between the legs sets the amplitude.
+    // The timer toggles the outputs at twice the carrier frequency.
+    PhaseShift,
+}
+
+impl OutputStage {
+    // Timer periods that every sample of the envelope takes. In phase shift mode both halves of
+    // the carrier period are a timer period.
+    pub const fn periods_per_sample(self) -> usize {
+        match self {
+            OutputStage::Duty => 1,
+            OutputStage::PhaseShift => 2,
+        }
+    }
+}
+
+// ARR, RCR, CCR1 and CCR2, written by a DMA burst on every update of TIM1
+pub const FRAME_LEN: usize = 4;
+
+pub type PwmFrame = [u16; FRAME_LEN];
+
+// Returns the values that the DMA burst writes for a timer period of `period` ticks.
+// ARR and the compare registers are preloaded, so they take effect at the update after the burst.
+pub fn pwm_frame(period: u16, ccr1: u16, ccr2: u16) -> PwmFrame {
+    [period - 1, 0, ccr1, ccr2]
+}
+
+// Returns the Q15 level of the carrier, which is the level of the ramp scaled by the Q15 gains
+// of the protections
+pub fn level(ramp: i32, gains: &[i32]) -> i32 {
+    gains.iter().fold(ramp, |level, gain| (level * gain) >> 15)
+}
+
+// Returns the frame of every timer period of a sample. `period` is the carrier period in ticks,
+// `amplitude` the Q15 amplitude of the envelope and `level` the Q15 level of the carrier, which
+// scales the amplitude in both stages.
+pub fn frame(stage: OutputStage, period: u16, amplitude: i32, level: i32) -> PwmFrame {
+    let amplitude = (amplitude * level) >> 15;
+    match stage {
+        OutputStage::Duty => {
+            let duty = duty(amplitude, period);
+            pwm_frame(period, duty, duty)
+        }
+        OutputStage::PhaseShift => {
+            let half_period = period / 2;
+            let (first, second) = leg_compares(amplitude, half_period);
+            pwm_frame(half_period, first, second)
+        }
+    }
+}
+
+pub struct Ramp {
+    // Q30
+    level: i32,
+    // Samples of the full ramp
+    time: u32,
+    // Q30 per sample
+    step: i32,
+}
+
+impl Ramp {
+    // The carrier is off on power up
+    pub const fn new() -> Self {
+        Self {
+            level: 0,
+            time: 1,
+            step: UNITY_GAIN << 15,
+        }
+    }
+
+    // Q15 amplitude of the carrier
+    pub fn level(&self) -> i32 {
+        self.level >> 15
+    }
+
+    pub fn is_off(&self) -> bool {
+        self.level == 0
+    }
+
+    // Moves towards the carrier being on or off. A full ramp takes `time` samples.
+    pub fn update(&mut self, on: bool, time: u32) -> i32 {
+        if time != self.time {
+            self.time = time.max(1);
+            self.step = (UNITY_GAIN << 15) / self.time as i32;
+        }
+
+        // A ramp of one sample steps by the whole range
+        self.level = if on {
+            self.level.saturating_add(self.step).min(UNITY_GAIN << 15)
+        } else {
+            self.level.saturating_sub(self.step).max(0)
+        };
+        self.level()
+    }
+}
+
+impl Default for Ramp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Returns the duty in ticks of both legs in duty mode for the Q15 amplitude.
+//
+// The legs have opposite polarity, so the bridge voltage is +V for the duty and -V for the rest
+// of the period. Its fundamental is sin(π · duty / period), which is full at a 50% duty.
+pub fn duty(amplitude: i32, period: u16) -> u16 {
+    let amplitude = amplitude.clamp(0, UNITY_GAIN) as f32 / UNITY_GAIN as f32;
+    let duty = libm::asinf(amplitude) * core::f32::consts::FRAC_1_PI * period as f32;
+    // Rounded, the compare value is at most half of the period
+    (duty + 0.5) as u16
+}
+
+// Returns the compare values of the two legs in phase shift mode for the Q15 amplitude.
+// `half_period` is the timer period, which is half of the carrier period.
+//
+// The second leg toggles `shift` ticks after the first one. Without a shift the legs are in
+// antiphase and the amplitude is full. A shift of a whole timer period puts them in phase.
+// In between, the fundamental is cos(π/2 · shift / half_period).
+pub fn leg_compares(amplitude: i32, half_period: u16) -> (u16, u16) {
+    let amplitude = amplitude.clamp(0, UNITY_GAIN) as f32 / UNITY_GAIN as f32;
+    let shift = libm::acosf(amplitude) * core::f32::consts::FRAC_2_PI * half_period as f32;
+    // The compare value has to stay within the timer period
+    let shift = (shift as u16).min(half_period - 1);
+    let first = (half_period - shift) / 2;
+    (first, first + shift)
+}
@@ -0,0 +1,394 @@
+// Runtime configuration that can be changed over USB
+
+use crate::battery::Chemistry;
+use crate::carrier::OutputStage;
+use crate::dead_time::{MAX_DEAD_TIME_NS, MIN_DEAD_TIME_NS};
+use crate::dynamics::Preset;
+use crate::eq::{self, Band, BandType};
+use crate::gate::GateSettings;
+use crate::modulation::Modulation;
+use crate::source::{Source, SourceMode};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum ConfigError {
+    UnknownParam,
+    InvalidValue,
+}
+
+// Identifiers of the parameters as used by the USB control requests
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+#[repr(u16)]
+pub enum Param {
+    SourceMode = 0,
+    Modulation = 1,
+    // Percent
+    ModulationIndex = 2,
+    // Hz, 0 disables the Berktay equalization
+    BerktayCorner = 3,
+    // Hz
+    BerktayHighPass = 4,
+    // Tenths of a dB relative to full scale
+    LimiterThreshold = 5,
+    // µs
+    LimiterAttack = 6,
+    // ms
+    LimiterRelease = 7,
+    UsbDynamics = 8,
+    AnalogDynamics = 9,
+    // µs
+    FaultRamp = 10,
+    OutputStage = 11,
+    // ns
+    DeadTime = 12,
+    BatteryChemistry = 13,
+    // Tenths of a °C
+    ThermalDerate = 14,
+    ThermalTrip = 15,
+    // mA
+    OvercurrentTrip = 16,
+    CurrentLimit = 17,
+    // Equalizer band and field, 0x100 + 4·band + field
+    Eq(u8, EqField),
+    // Noise gate of a source and field, 0x200 + 4·source + field
+    Gate(Source, GateField),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum EqField {
+    Type,
+    // Hz
+    Frequency,
+    // Hundredths
+    Q,
+    // Tenths of a dB
+    Gain,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum GateField {
+    // Tenths of a dB relative to full scale
+    Open,
+    Close,
+    // ms
+    Hold,
+    Fade,
+}
+
+impl TryFrom<u16> for Param {
+    type Error = ConfigError;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Param::SourceMode),
+            1 => Ok(Param::Modulation),
+            2 => Ok(Param::ModulationIndex),
+            3 => Ok(Param::BerktayCorner),
+            4 => Ok(Param::BerktayHighPass),
+            5 => Ok(Param::LimiterThreshold),
+            6 => Ok(Param::LimiterAttack),
+            7 => Ok(Param::LimiterRelease),
+            8 => Ok(Param::UsbDynamics),
+            9 => Ok(Param::AnalogDynamics),
+            10 => Ok(Param::FaultRamp),
+            11 => Ok(Param::OutputStage),
+            12 => Ok(Param::DeadTime),
+            13 => Ok(Param::BatteryChemistry),
+            14 => Ok(Param::ThermalDerate),
+            15 => Ok(Param::ThermalTrip),
+            16 => Ok(Param::OvercurrentTrip),
+            17 => Ok(Param::CurrentLimit),
+            0x100..=0x1ff if ((value - 0x100) / 4) < eq::BANDS as u16 => {
+                let field = match value % 4 {
+                    0 => EqField::Type,
+                    1 => EqField::Frequency,
+                    2 => EqField::Q,
+                    _ => EqField::Gain,
+                };
+                Ok(Param::Eq(((value - 0x100) / 4) as u8, field))
+            }
+            0x200..=0x207 => {
+                let source = match (value - 0x200) / 4 {
+                    0 => Source::Usb,
+                    _ => Source::Analog,
+                };
+                let field = match value % 4 {
+                    0 => GateField::Open,
+                    1 => GateField::Close,
+                    2 => GateField::Hold,
+                    _ => GateField::Fade,
+                };
+                Ok(Param::Gate(source, field))
+            }
+            _ => Err(ConfigError::UnknownParam),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Config {
+    pub source_mode: SourceMode,
+    pub modulation: Modulation,
+    // Percent
+    pub modulation_index: i32,
+    // Hz
+    pub berktay_corner: i32,
+    pub berktay_high_pass: i32,
+    // Tenths of a dB
+    pub limiter_threshold: i32,
+    // µs
+    pub limiter_attack: i32,
+    // ms
+    pub limiter_release: i32,
+    pub usb_dynamics: Preset,
+    pub analog_dynamics: Preset,
+    // µs, the time in which the carrier is ramped down on a fault
+    pub fault_ramp: i32,
+    pub output_stage: OutputStage,
+    // ns
+    pub dead_time: i32,
+    pub battery_chemistry: Chemistry,
+    // Tenths of a °C of the hotter of the NTC and the internal sensor. The output is derated
+    // above the first and switched off at the second.
+    pub thermal_derate: i32,
+    pub thermal_trip: i32,
+    // mA, the peak current that kills the H-bridge and the average current it is limited to
+    pub overcurrent_trip: i32,
+    pub current_limit: i32,
+    pub eq: [Band; eq::BANDS],
+    // Indexed by Source
+    pub gates: [GateSettings; 2],
+}
+
+impl Config {
+    pub const DEFAULT: Self = Self {
+        source_mode: SourceMode::Auto,
+        modulation: Modulation::Dsb,
+        modulation_index: 100,
+        berktay_corner: 0,
+        berktay_high_pass: 40,
+        limiter_threshold: -10,
+        limiter_attack: 200,
+        limiter_release: 100,
+        usb_dynamics: Preset::Off,
+        analog_dynamics: Preset::Off,
+        fault_ramp: 2000,
+        output_stage: OutputStage::Duty,
+        dead_time: 300,
+        battery_chemistry: Chemistry::LiIon3S,
+        thermal_derate: 700,
+        thermal_trip: 950,
+        overcurrent_trip: 5000,
+        current_limit: 2000,
+        eq: [
+            Band::off(100),
+            Band::off(1000),
+            Band::off(4000),
+            Band::off(10000),
+        ],
+        gates: [GateSettings::DEFAULT; 2],
+    };
+
+    pub fn get(&self, param: Param) -> i32 {
+        match param {
+            Param::SourceMode => self.source_mode as i32,
+            Param::Modulation => self.modulation as i32,
+            Param::ModulationIndex => self.modulation_index,
+            Param::BerktayCorner => self.berktay_corner,
+            Param::BerktayHighPass => self.berktay_high_pass,
+            Param::LimiterThreshold => self.limiter_threshold,
+            Param::LimiterAttack => self.limiter_attack,
+            Param::LimiterRelease => self.limiter_release,
+            Param::UsbDynamics => self.usb_dynamics as i32,
+            Param::AnalogDynamics => self.analog_dynamics as i32,
+            Param::FaultRamp => self.fault_ramp,
+            Param::OutputStage => self.output_stage as i32,
+            Param::DeadTime => self.dead_time,
+            Param::BatteryChemistry => self.battery_chemistry as i32,
+            Param::ThermalDerate => self.thermal_derate,
+            Param::ThermalTrip => self.thermal_trip,
+            Param::OvercurrentTrip => self.overcurrent_trip,
+            Param::CurrentLimit => self.current_limit,
+            Param::Eq(band, field) => {
+                let band = &self.eq[band as usize];
+                match field {
+                    EqField::Type => band.kind as i32,
+                    EqField::Frequency => band.frequency,
+                    EqField::Q => band.q,
+                    EqField::Gain => band.gain,
+                }
+            }
+            Param::Gate(source, field) => {
+                let gate = &self.gates[source as usize];
+                match field {
+                    GateField::Open => gate.open,
+                    GateField::Close => gate.close,
+                    GateField::Hold => gate.hold,
+                    GateField::Fade => gate.fade,
+                }
+            }
+        }
+    }
+
+    pub fn set(&mut self, param: Param, value: i32) -> Result<(), ConfigError> {
+        match param {
+            Param::SourceMode => {
+                self.source_mode = match value {
+                    0 => SourceMode::Auto,
+                    1 => SourceMode::ForceUsb,
+                    2 => SourceMode::ForceAnalog,
+                    _ => return Err(ConfigError::InvalidValue),
+                }
+            }
+            Param::Modulation => {
+                self.modulation = match value {
+                    0 => Modulation::Dsb,
+                    1 => Modulation::Sram,
+                    2 => Modulation::UpperSsb,
+                    3 => Modulation::LowerSsb,
+                    _ => return Err(ConfigError::InvalidValue),
+                }
+            }
+            Param::ModulationIndex => {
+                if !(0..=100).contains(&value) {
+                    return Err(ConfigError::InvalidValue);
+                }
+                self.modulation_index = value;
+            }
+            Param::BerktayCorner => {
+                if !(0..=5000).contains(&value) {
+                    return Err(ConfigError::InvalidValue);
+                }
+                self.berktay_corner = value;
+            }
+            Param::BerktayHighPass => {
+                if !(1..=1000).contains(&value) {
+                    return Err(ConfigError::InvalidValue);
+                }
+                self.berktay_high_pass = value;
+            }
+            Param::LimiterThreshold => {
+                if !(-200..=0).contains(&value) {
+                    return Err(ConfigError::InvalidValue);
+                }
+                self.limiter_threshold = value;
+            }
+            // The attack has to be over well within the look-ahead time
+            Param::LimiterAttack => {
+                if !(10..=500).contains(&value) {
+                    return Err(ConfigError::InvalidValue);
+                }
+                self.limiter_attack = value;
+            }
+            Param::LimiterRelease => {
+                if !(1..=2000).contains(&value) {
+                    return Err(ConfigError::InvalidValue);
+                }
+                self.limiter_release = value;
+            }
+            Param::UsbDynamics => self.usb_dynamics = preset(value)?,
+            Param::AnalogDynamics => self.analog_dynamics = preset(value)?,
+            Param::FaultRamp => {
+                if !(25..=100_000).contains(&value) {
+                    return Err(ConfigError::InvalidValue);
+                }
+                self.fault_ramp = value;
+            }
+            Param::OutputStage => {
+                self.output_stage = match value {
+                    0 => OutputStage::Duty,
+                    1 => OutputStage::PhaseShift,
+                    _ => return Err(ConfigError::InvalidValue),
+                }
+            }
+            Param::DeadTime => {
+                if !(MIN_DEAD_TIME_NS as i32..=MAX_DEAD_TIME_NS as i32).contains(&value) {
+                    return Err(ConfigError::InvalidValue);
+                }
+                self.dead_time = value;
+            }
+            Param::BatteryChemistry => {
+                self.battery_chemistry = match value {
+                    0 => Chemistry::Off,
+                    1 => Chemistry::LiIon2S,
+                    2 => Chemistry::LiIon3S,
+                    3 => Chemistry::LiIon4S,
+                    4 => Chemistry::LeadAcid,
+                    _ => return Err(ConfigError::InvalidValue),
+                }
+            }
+            // The derating always starts below the trip temperature
+            Param::ThermalDerate => {
+                if !(300..self.thermal_trip).contains(&value) {
+                    return Err(ConfigError::InvalidValue);
+                }
+                self.thermal_derate = value;
+            }
+            Param::ThermalTrip => {
+                if !(self.thermal_derate + 1..=1250).contains(&value) {
+                    return Err(ConfigError::InvalidValue);
+                }
+                self.thermal_trip = value;
+            }
+            // 6.6A is the full scale of the current measurement. The average current is always
+            // limited below the trip current.
+            Param::OvercurrentTrip => {
+                if !(self.current_limit + 1..=6000).contains(&value) {
+                    return Err(ConfigError::InvalidValue);
+                }
+                self.overcurrent_trip = value;
+            }
+            Param::CurrentLimit => {
+                if !(100..self.overcurrent_trip).contains(&value) {
+                    return Err(ConfigError::InvalidValue);
+                }
+                self.current_limit = value;
+            }
+            Param::Eq(band, field) => {
+                let band = &mut self.eq[band as usize];
+                match field {
+                    EqField::Type => {
+                        band.kind = match value {
+                            0 => BandType::Off,
+                            1 => BandType::Peaking,
+                            2 => BandType::LowShelf,
+                            3 => BandType::HighShelf,
+                            4 => BandType::HighPass,
+                            5 => BandType::LowPass,
+                            _ => return Err(ConfigError::InvalidValue),
+                        }
+                    }
+                    EqField::Frequency if (10..=19000).contains(&value) => band.frequency = value,
+                    EqField::Q if (10..=1000).contains(&value) => band.q = value,
+                    EqField::Gain if (-240..=240).contains(&value) => band.gain = value,
+                    _ => return Err(ConfigError::InvalidValue),
+                }
+            }
+            Param::Gate(source, field) => {
+                let gate = &mut self.gates[source as usize];
+                match field {
+                    GateField::Open if (-900..=0).contains(&value) => gate.open = value,
+                    GateField::Close if (-900..=0).contains(&value) => gate.close = value,
+                    GateField::Hold if (0..=10000).contains(&value) => gate.hold = value,
+                    GateField::Fade if (1..=1000).contains(&value) => gate.fade = value,
+                    _ => return Err(ConfigError::InvalidValue),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn preset(value: i32) -> Result<Preset, ConfigError> {
+    match value {
+        0 => Ok(Preset::Off),
+        1 => Ok(Preset::Speech),
+        2 => Ok(Preset::Music),
+        _ => Err(ConfigError::InvalidValue),
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
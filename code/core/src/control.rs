@@ -0,0 +1,122 @@
+// Vendor specific USB interface for changing the configuration at runtime
+//
+// SET_PARAM: OUT request, wValue = parameter, data = i32 little endian
+// GET_PARAM: IN request, wValue = parameter, data = i32 little endian
+// GET_TELEMETRY: IN request, wValue = measurement, data = i32 little endian
+
+use defmt::warn;
+use usb_device::{
+    class_prelude::*,
+    control::{Recipient, Request, RequestType},
+    Result,
+};
+
+use crate::config::{Config, ConfigError, Param};
+use crate::telemetry::{Measurement, Telemetry};
+
+pub const REQUEST_SET_PARAM: u8 = 0x01;
+pub const REQUEST_GET_PARAM: u8 = 0x02;
+pub const REQUEST_GET_TELEMETRY: u8 = 0x03;
+
+const VENDOR_SPECIFIC: u8 = 0xff;
+
+// Decodes a SET_PARAM request from its wValue and data, and applies it to `config`
+pub fn set_param(
+    config: &mut Config,
+    id: u16,
+    data: &[u8],
+) -> core::result::Result<(), ConfigError> {
+    let param = Param::try_from(id)?;
+    let value = data
+        .try_into()
+        .map(i32::from_le_bytes)
+        .map_err(|_| ConfigError::InvalidValue)?;
+    config
+        .set(param, value)
+        .inspect_err(|e| warn!("Rejected {} = {}: {}", param, value, e))
+}
+
+// Answers a GET_PARAM request for the parameter in wValue
+pub fn get_param(config: &Config, id: u16) -> core::result::Result<[u8; 4], ConfigError> {
+    Param::try_from(id).map(|param| config.get(param).to_le_bytes())
+}
+
+pub struct ControlClass {
+    iface: InterfaceNumber,
+    config: Config,
+    changed: bool,
+    telemetry: Telemetry,
+}
+
+impl ControlClass {
+    pub fn new<B: UsbBus>(alloc: &UsbBusAllocator<B>, config: Config) -> Self {
+        Self {
+            iface: alloc.interface(),
+            config,
+            changed: false,
+            telemetry: Telemetry::default(),
+        }
+    }
+
+    // Returns the configuration if the host has changed it since the last call
+    pub fn take_changed(&mut self) -> Option<Config> {
+        if self.changed {
+            self.changed = false;
+            Some(self.config)
+        } else {
+            None
+        }
+    }
+
+    pub fn set_telemetry(&mut self, telemetry: Telemetry) {
+        self.telemetry = telemetry;
+    }
+
+    fn is_for_us(&self, req: &Request) -> bool {
+        req.request_type == RequestType::Vendor
+            && req.recipient == Recipient::Interface
+            && req.index == u8::from(self.iface) as u16
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for ControlClass {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.iface, VENDOR_SPECIFIC, 0, 0)
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+        if !self.is_for_us(&req) {
+            return;
+        }
+
+        match req.request {
+            REQUEST_GET_PARAM => match get_param(&self.config, req.value) {
+                Ok(data) => xfer.accept_with(&data),
+                Err(_) => xfer.reject(),
+            },
+            REQUEST_GET_TELEMETRY => match Measurement::try_from(req.value) {
+                Ok(measurement) => xfer.accept_with(&self.telemetry.get(measurement).to_le_bytes()),
+                Err(_) => xfer.reject(),
+            },
+            _ => xfer.reject(),
+        }
+        .ok();
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+        if !self.is_for_us(&req) {
+            return;
+        }
+
+        if req.request == REQUEST_SET_PARAM
+            && set_param(&mut self.config, req.value, xfer.data()).is_ok()
+        {
+            self.changed = true;
+            xfer.accept().ok();
+        } else {
+            xfer.reject().ok();
+        }
+    }
+}
@@ -0,0 +1,128 @@
+// Output current of the H-bridge and limiting of the average current
+//
+// The current is measured on a low-side shunt, amplified by an INA180A2, and sampled by ADC1 at
+// the start of every period of TIM1, so always at the same point of the carrier period. The DMA
+// writes the samples into a circular buffer. Peaks above the trip current are caught by the
+// analog watchdog of the ADC, which kills the H-bridge. The average current is limited by reducing
+// the carrier amplitude. The limiter runs once per block of the PWM buffer on the mean of the
+// samples of the last block.
+
+use crate::filter;
+use crate::gain::UNITY_GAIN;
+
+pub const SHUNT_MILLIOHM: u32 = 10;
+pub const AMPLIFIER_GAIN: u32 = 50;
+
+const ADC_FULL_SCALE: u32 = 4095;
+// µs
+const AVERAGE_TIME: u32 = 100_000;
+// The gain follows the average current slower than the average follows the current, so that the
+// limiting does not oscillate
+const ATTACK_TIME: u32 = 200_000;
+const RELEASE_TIME: u32 = 1_000_000;
+
+// Returns the current in mA for an ADC sample with VDDA in mV
+pub fn current(sample: u16, vdda: u32) -> i32 {
+    let voltage = sample as u32 * vdda / ADC_FULL_SCALE;
+    (voltage * 1000 / (SHUNT_MILLIOHM * AMPLIFIER_GAIN)) as i32
+}
+
+// Returns the ADC sample of a current in mA with VDDA in mV
+pub fn sample(current: i32, vdda: u32) -> u16 {
+    let voltage = current.max(0) as u32 * SHUNT_MILLIOHM * AMPLIFIER_GAIN / 1000;
+    (voltage * ADC_FULL_SCALE / vdda).min(ADC_FULL_SCALE) as u16
+}
+
+// Sums the samples of the current until the mean is taken
+#[derive(Clone, Copy, Default)]
+pub struct SampleSum {
+    sum: u32,
+    count: u32,
+}
+
+impl SampleSum {
+    pub const fn new() -> Self {
+        Self { sum: 0, count: 0 }
+    }
+
+    pub fn add(&mut self, sample: u16) {
+        self.sum += sample as u32;
+        self.count += 1;
+    }
+
+    // Returns the mean of the samples since the last call, 0 without any
+    pub fn take(&mut self) -> u16 {
+        let Self { sum, count } = core::mem::take(self);
+        sum.checked_div(count).unwrap_or(0) as u16
+    }
+}
+
+pub struct CurrentLimiter {
+    // mA
+    limit: i32,
+    // Q24
+    average_k: i64,
+    attack: i64,
+    release: i64,
+    // µA
+    average: i32,
+    // Q30
+    gain: i32,
+}
+
+impl CurrentLimiter {
+    pub const fn new() -> Self {
+        Self {
+            limit: i32::MAX,
+            average_k: filter::ONE,
+            attack: filter::ONE,
+            release: filter::ONE,
+            average: 0,
+            gain: UNITY_GAIN << 15,
+        }
+    }
+
+    // `limit` is the average current in mA. `rate` is the rate in Hz at which `process` is called.
+    pub fn set_limit(&mut self, limit: i32, rate: u32) {
+        self.limit = limit;
+        self.average_k = filter::time_constant(AVERAGE_TIME, rate);
+        self.attack = filter::time_constant(ATTACK_TIME, rate);
+        self.release = filter::time_constant(RELEASE_TIME, rate);
+    }
+
+    // Average current in mA
+    pub fn average(&self) -> i32 {
+        self.average / 1000
+    }
+
+    // Q15
+    pub fn gain(&self) -> i32 {
+        self.gain >> 15
+    }
+
+    // Takes the current in mA and returns the Q15 gain of the carrier
+    pub fn process(&mut self, current: i32) -> i32 {
+        self.average += (((current * 1000 - self.average) as i64 * self.average_k) >> 24) as i32;
+
+        // The current is roughly proportional to the gain, so this gain would just reach the limit
+        let average = self.average();
+        let target = if average > self.limit {
+            (self.limit as i64 * self.gain() as i64 / average as i64) as i32
+        } else {
+            UNITY_GAIN
+        };
+        let k = if (target << 15) < self.gain {
+            self.attack
+        } else {
+            self.release
+        };
+        self.gain += ((((target << 15) - self.gain) as i64 * k) >> 24) as i32;
+        self.gain()
+    }
+}
+
+impl Default for CurrentLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,46 @@
+// Dead-time between the complementary TIM1 outputs that drive the gate drivers
+
+// Both MOSFETs of a leg must never conduct at the same time.
+// Shorter dead-times are raised to this, whatever the configuration says.
+pub const MIN_DEAD_TIME_NS: u32 = 200;
+// A tenth of the half period at 40kHz
+pub const MAX_DEAD_TIME_NS: u32 = 1250;
+
+// Returns the DTG bits of TIM1_BDTR for a dead-time of at least `ns` with the timer clocked at
+// `clock` Hz (CKD = 0). The encoding has four ranges of increasing step:
+//
+// 0xxxxxxx: DTG[7:0] ticks
+// 10xxxxxx: (64 + DTG[5:0]) · 2 ticks
+// 110xxxxx: (32 + DTG[4:0]) · 8 ticks
+// 111xxxxx: (32 + DTG[4:0]) · 16 ticks
+//
+// Dead-times that fall between two steps are rounded up, longer ones than the encoding allows
+// are limited to the maximum.
+pub fn dtg(ns: u32, clock: u32) -> u8 {
+    let ns = ns.max(MIN_DEAD_TIME_NS) as u64;
+    let ticks = (ns * clock as u64).div_ceil(1_000_000_000);
+
+    match ticks {
+        0..=127 => ticks as u8,
+        128..=254 => 0b1000_0000 | (ticks.div_ceil(2) - 64) as u8,
+        255..=504 => 0b1100_0000 | (ticks.div_ceil(8) - 32) as u8,
+        505..=1008 => 0b1110_0000 | (ticks.div_ceil(16) - 32) as u8,
+        _ => 0xff,
+    }
+}
+
+// Returns the dead-time in timer ticks that the DTG bits encode
+pub fn ticks(dtg: u8) -> u32 {
+    let dtg = dtg as u32;
+    match dtg >> 5 {
+        0b000..=0b011 => dtg,
+        0b100 | 0b101 => (64 + (dtg & 0x3f)) * 2,
+        0b110 => (32 + (dtg & 0x1f)) * 8,
+        _ => (32 + (dtg & 0x1f)) * 16,
+    }
+}
+
+// Returns the dead-time in ns that the DTG bits encode
+pub fn dead_time_ns(dtg: u8, clock: u32) -> u32 {
+    (ticks(dtg) as u64 * 1_000_000_000 / clock as u64) as u32
+}
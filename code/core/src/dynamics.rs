@@ -0,0 +1,280 @@
+// Automatic gain control and 3-band compressor, so that every source ends up at a similar
+// level and speech stays intelligible
+//
+// The audio path is integer. The gains are computed in dB with the FPU at a lower rate, one
+// gain per sample in turn, so that no sample has to do more than one of them.
+
+use crate::filter::{self, OnePole, ONE};
+use crate::gain::UNITY_GAIN;
+
+const BANDS: usize = 3;
+// Every gain is recomputed once per this many samples
+const CONTROL_PERIOD: u32 = 16;
+// The samples in the bands are shifted left by this many bits for precision
+const SAMPLE_SHIFT: u32 = 8;
+// Full scale of the shifted samples
+const FULL_SCALE: f32 = (i16::MAX as i32 * (1 << SAMPLE_SHIFT)) as f32;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Preset {
+    Off,
+    Speech,
+    Music,
+}
+
+struct BandSettings {
+    // dB relative to full scale
+    threshold: f32,
+    ratio: f32,
+    // dB
+    makeup: f32,
+}
+
+struct Settings {
+    // Hz, between the low and the mid and between the mid and the high band
+    crossovers: [u32; 2],
+    bands: [BandSettings; BANDS],
+    // µs
+    attack: u32,
+    release: u32,
+    // dB relative to full scale
+    agc_target: f32,
+    // dB
+    agc_max_gain: f32,
+    agc_min_gain: f32,
+    // The AGC holds its gain below this level, so that noise is not amplified.
+    // dB relative to full scale.
+    agc_gate: f32,
+    // µs
+    agc_attack: u32,
+    agc_release: u32,
+}
+
+// Strong AGC and a presence boost. Rumble is compressed hard.
+const SPEECH: Settings = Settings {
+    crossovers: [300, 3000],
+    bands: [
+        BandSettings {
+            threshold: -30.0,
+            ratio: 4.0,
+            makeup: -3.0,
+        },
+        BandSettings {
+            threshold: -24.0,
+            ratio: 3.0,
+            makeup: 4.0,
+        },
+        BandSettings {
+            threshold: -28.0,
+            ratio: 2.0,
+            makeup: 2.0,
+        },
+    ],
+    attack: 5_000,
+    release: 100_000,
+    agc_target: -18.0,
+    agc_max_gain: 24.0,
+    agc_min_gain: -12.0,
+    agc_gate: -50.0,
+    agc_attack: 500_000,
+    agc_release: 2_000_000,
+};
+
+// Gentle AGC and compression that keep the dynamics of the music
+const MUSIC: Settings = Settings {
+    crossovers: [200, 4000],
+    bands: [
+        BandSettings {
+            threshold: -18.0,
+            ratio: 2.0,
+            makeup: 0.0,
+        },
+        BandSettings {
+            threshold: -18.0,
+            ratio: 1.5,
+            makeup: 0.0,
+        },
+        BandSettings {
+            threshold: -20.0,
+            ratio: 1.5,
+            makeup: 0.0,
+        },
+    ],
+    attack: 10_000,
+    release: 300_000,
+    agc_target: -16.0,
+    agc_max_gain: 12.0,
+    agc_min_gain: -12.0,
+    agc_gate: -55.0,
+    agc_attack: 2_000_000,
+    agc_release: 8_000_000,
+};
+
+// Follows the level of a signal with separate attack and release times
+struct Envelope {
+    // Q24
+    attack: i64,
+    release: i64,
+    level: i32,
+}
+
+impl Envelope {
+    const fn new() -> Self {
+        Self {
+            attack: ONE,
+            release: ONE,
+            level: 0,
+        }
+    }
+
+    fn set_times(&mut self, attack: u32, release: u32, sample_rate: u32) {
+        self.attack = filter::time_constant(attack, sample_rate);
+        self.release = filter::time_constant(release, sample_rate);
+    }
+
+    fn process(&mut self, x: i32) -> i32 {
+        let x = x.abs();
+        let k = if x > self.level {
+            self.attack
+        } else {
+            self.release
+        };
+        self.level += (((x - self.level) as i64 * k) >> 24) as i32;
+        self.level
+    }
+
+    // dB relative to full scale
+    fn db(&self) -> f32 {
+        20.0 * libm::log10f(self.level.max(1) as f32 / FULL_SCALE)
+    }
+}
+
+fn db_to_gain(db: f32) -> i32 {
+    (libm::powf(10.0, db / 20.0) * UNITY_GAIN as f32) as i32
+}
+
+// Q15 gain that moves towards its target over a control period
+struct Gain {
+    target: i32,
+    current: i32,
+}
+
+impl Gain {
+    const fn new() -> Self {
+        Self {
+            target: UNITY_GAIN,
+            current: UNITY_GAIN,
+        }
+    }
+
+    fn next(&mut self) -> i32 {
+        self.current += (self.target - self.current) / CONTROL_PERIOD as i32;
+        self.current
+    }
+}
+
+pub struct Dynamics {
+    settings: Option<&'static Settings>,
+    crossovers: [OnePole; 2],
+    agc_envelope: Envelope,
+    agc_gain: Gain,
+    // dB
+    agc_db: f32,
+    envelopes: [Envelope; BANDS],
+    gains: [Gain; BANDS],
+    time: u32,
+}
+
+impl Dynamics {
+    pub const fn new() -> Self {
+        Self {
+            settings: None,
+            crossovers: [OnePole::new(), OnePole::new()],
+            agc_envelope: Envelope::new(),
+            agc_gain: Gain::new(),
+            agc_db: 0.0,
+            envelopes: [Envelope::new(), Envelope::new(), Envelope::new()],
+            gains: [Gain::new(), Gain::new(), Gain::new()],
+            time: 0,
+        }
+    }
+
+    // The levels and gains are kept, so changing the preset does not cause a jump
+    pub fn set_preset(&mut self, preset: Preset, sample_rate: u32) {
+        let settings = match preset {
+            Preset::Off => {
+                self.settings = None;
+                return;
+            }
+            Preset::Speech => &SPEECH,
+            Preset::Music => &MUSIC,
+        };
+        self.settings = Some(settings);
+
+        for (crossover, frequency) in self.crossovers.iter_mut().zip(settings.crossovers) {
+            crossover.set_coefficient(filter::corner(frequency, sample_rate));
+        }
+        self.agc_envelope
+            .set_times(settings.agc_attack, settings.agc_release, sample_rate);
+        for envelope in &mut self.envelopes {
+            envelope.set_times(settings.attack, settings.release, sample_rate);
+        }
+    }
+
+    pub fn process(&mut self, x: i16) -> i16 {
+        let Some(settings) = self.settings else {
+            return x;
+        };
+
+        let x = (x as i32) << SAMPLE_SHIFT;
+        self.agc_envelope.process(x);
+        let x = ((x as i64 * self.agc_gain.next() as i64) >> 15) as i32;
+
+        // The bands add up to the input exactly
+        let low = self.crossovers[0].process(x);
+        let rest = x - low;
+        let mid = self.crossovers[1].process(rest);
+        let high = rest - mid;
+
+        let mut y: i64 = 0;
+        for (i, band) in [low, mid, high].into_iter().enumerate() {
+            self.envelopes[i].process(band);
+            y += band as i64 * self.gains[i].next() as i64;
+        }
+
+        match self.time % CONTROL_PERIOD {
+            0 => self.update_agc(settings),
+            n if (n as usize) <= BANDS => self.update_band(settings, n as usize - 1),
+            _ => (),
+        }
+        self.time = self.time.wrapping_add(1);
+
+        (y >> (15 + SAMPLE_SHIFT)).clamp(i16::MIN as i64, i16::MAX as i64) as i16
+    }
+
+    fn update_agc(&mut self, settings: &Settings) {
+        let level = self.agc_envelope.db();
+        if level > settings.agc_gate {
+            self.agc_db =
+                (settings.agc_target - level).clamp(settings.agc_min_gain, settings.agc_max_gain);
+        }
+        self.agc_gain.target = db_to_gain(self.agc_db);
+    }
+
+    fn update_band(&mut self, settings: &Settings, band: usize) {
+        let band_settings = &settings.bands[band];
+        let over = self.envelopes[band].db() - band_settings.threshold;
+        let reduction = if over > 0.0 {
+            over * (1.0 - 1.0 / band_settings.ratio)
+        } else {
+            0.0
+        };
+        self.gains[band].target = db_to_gain(band_settings.makeup - reduction);
+    }
+}
+
+impl Default for Dynamics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,147 @@
+// Parametric equalizer of cascaded biquads to compensate for the transducers and the room
+
+use idsp::iir::Biquad;
+use libm::{cosf, powf, sinf, sqrtf};
+
+pub const BANDS: usize = 4;
+
+// Headroom of the samples inside the filters for boosting bands
+const SAMPLE_SHIFT: u32 = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum BandType {
+    Off,
+    Peaking,
+    LowShelf,
+    HighShelf,
+    HighPass,
+    LowPass,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Band {
+    pub kind: BandType,
+    // Hz
+    pub frequency: i32,
+    // Hundredths
+    pub q: i32,
+    // Tenths of a dB, unused by the high-pass and the low-pass
+    pub gain: i32,
+}
+
+impl Band {
+    pub const fn off(frequency: i32) -> Self {
+        Self {
+            kind: BandType::Off,
+            frequency,
+            q: 71,
+            gain: 0,
+        }
+    }
+}
+
+// Returns [b0, b1, b2, a0, a1, a2] of the band from the Audio EQ Cookbook by Robert Bristow-Johnson
+pub fn coefficients(band: &Band, sample_rate: u32) -> Option<[f32; 6]> {
+    let w0 = 2.0 * core::f32::consts::PI * band.frequency as f32 / sample_rate as f32;
+    let (sin, cos) = (sinf(w0), cosf(w0));
+    let alpha = sin / (2.0 * band.q as f32 / 100.0);
+    let a = powf(10.0, band.gain as f32 / 400.0);
+
+    Some(match band.kind {
+        BandType::Off => return None,
+        BandType::Peaking => [
+            1.0 + alpha * a,
+            -2.0 * cos,
+            1.0 - alpha * a,
+            1.0 + alpha / a,
+            -2.0 * cos,
+            1.0 - alpha / a,
+        ],
+        BandType::LowShelf => {
+            let sqrt_a = 2.0 * sqrtf(a) * alpha;
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a),
+                (a + 1.0) + (a - 1.0) * cos + sqrt_a,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - sqrt_a,
+            ]
+        }
+        BandType::HighShelf => {
+            let sqrt_a = 2.0 * sqrtf(a) * alpha;
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a),
+                (a + 1.0) - (a - 1.0) * cos + sqrt_a,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - sqrt_a,
+            ]
+        }
+        BandType::HighPass => [
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        ],
+        BandType::LowPass => [
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        ],
+    })
+}
+
+pub struct Equalizer {
+    bands: [Band; BANDS],
+    biquads: [Option<Biquad<i32>>; BANDS],
+    // x1, x2, y1, y2 of every biquad
+    state: [[i32; 4]; BANDS],
+}
+
+impl Equalizer {
+    pub const fn new() -> Self {
+        const NONE: Option<Biquad<i32>> = None;
+        Self {
+            bands: [Band::off(0); BANDS],
+            biquads: [NONE; BANDS],
+            state: [[0; 4]; BANDS],
+        }
+    }
+
+    // Only the bands that have changed are redesigned
+    pub fn set_bands(&mut self, bands: &[Band; BANDS], sample_rate: u32) {
+        for (i, band) in bands.iter().enumerate() {
+            if *band == self.bands[i] {
+                continue;
+            }
+            self.bands[i] = *band;
+            self.biquads[i] = coefficients(band, sample_rate).map(|ba| Biquad::from(&ba));
+            if self.biquads[i].is_none() {
+                self.state[i] = [0; 4];
+            }
+        }
+    }
+
+    pub fn process(&mut self, x: i16) -> i16 {
+        let mut y = (x as i32) << SAMPLE_SHIFT;
+        for (biquad, xy) in self.biquads.iter().zip(&mut self.state) {
+            if let Some(biquad) = biquad {
+                y = biquad.update(xy, y);
+            }
+        }
+        (y >> SAMPLE_SHIFT).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+    }
+}
+
+impl Default for Equalizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,65 @@
+// Causes for which the carrier is switched off
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Fault {
+    // These kill the H-bridge until the next reset
+    Panic,
+    HardFault,
+    Overcurrent,
+    // An interrupt handler has stopped running, the watchdog resets soon after
+    Stall,
+    // These ramp the carrier out and clear by themselves
+    Undervoltage,
+    Overtemperature,
+}
+
+impl Fault {
+    // Bit of the fault in a set of faults
+    pub const fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+// The faults that are active, and the number that have occurred since the last reset
+#[derive(Clone, Copy, Default)]
+pub struct Faults {
+    active: u32,
+    count: u32,
+}
+
+impl Faults {
+    pub const fn new() -> Self {
+        Self {
+            active: 0,
+            count: 0,
+        }
+    }
+
+    // Sets or clears a fault. Returns whether it has changed. Only setting it counts.
+    pub fn set(&mut self, fault: Fault, active: bool) -> bool {
+        let before = self.active;
+        if active {
+            self.active |= fault.bit();
+        } else {
+            self.active &= !fault.bit();
+        }
+        let changed = self.active != before;
+        if changed && active {
+            self.count += 1;
+        }
+        changed
+    }
+
+    pub fn any(&self) -> bool {
+        self.active != 0
+    }
+
+    // See Fault::bit
+    pub fn bits(&self) -> u32 {
+        self.active
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
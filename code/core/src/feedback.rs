@@ -0,0 +1,13 @@
+// Rate feedback for the asynchronous USB audio endpoint
+
+// 10.14 samples per frame for USB full speed
+pub const fn nominal_feedback(rate: u32) -> u32 {
+    (((rate as u64) << 14) / 1000) as u32
+}
+
+// Asks the host for slightly more or fewer samples than the nominal rate.
+// `correction` is the relative correction in Q32 as produced by the fill servo.
+pub fn feedback(rate: u32, correction: i64) -> u32 {
+    let nominal = nominal_feedback(rate) as i64;
+    (nominal + ((nominal * correction) >> 32)) as u32
+}
@@ -0,0 +1,53 @@
+// One pole filters shared by the audio processing stages
+
+// Q24
+pub const ONE: i64 = 1 << 24;
+
+// Returns the coefficient of a one pole low-pass with the corner frequency in Hz
+pub fn corner(frequency: u32, sample_rate: u32) -> i64 {
+    let w = 2.0 * core::f32::consts::PI * frequency as f32 / sample_rate as f32;
+    ((1.0 - libm::expf(-w)) * ONE as f32) as i64
+}
+
+// Returns the coefficient of a one pole low-pass with the time constant in µs
+pub fn time_constant(time: u32, sample_rate: u32) -> i64 {
+    let samples = time as f32 * sample_rate as f32 / 1e6;
+    ((1.0 - libm::expf(-1.0 / samples.max(1.0))) * ONE as f32) as i64
+}
+
+// One pole low-pass. Above the corner frequency it is an integrator.
+pub struct OnePole {
+    // Q24
+    k: i64,
+    y: i32,
+}
+
+impl OnePole {
+    pub const fn new() -> Self {
+        Self { k: ONE, y: 0 }
+    }
+
+    pub fn set_coefficient(&mut self, k: i64) {
+        self.k = k;
+    }
+
+    // Sets the output without a transient
+    pub fn reset(&mut self, y: i32) {
+        self.y = y;
+    }
+
+    pub fn output(&self) -> i32 {
+        self.y
+    }
+
+    pub fn process(&mut self, x: i32) -> i32 {
+        self.y += (((x as i64 - self.y as i64) * self.k) >> 24) as i32;
+        self.y
+    }
+}
+
+impl Default for OnePole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,65 @@
+// Volume control of the USB audio feature unit
+
+// Volume in 1/256 dB as defined by the USB audio class
+pub const VOLUME_MIN: i16 = -60 * 256;
+pub const VOLUME_MAX: i16 = 0;
+pub const VOLUME_RES: i16 = 256;
+
+pub const UNITY_GAIN: i32 = 1 << 15;
+
+// Q20 gain for every dB of attenuation from 0 to 60dB
+#[rustfmt::skip]
+const ATTENUATION_TO_GAIN: [i32; 61] = [
+    1048576, 934544, 832914, 742335, 661607, 589658, 525533, 468382,
+    417446, 372049, 331589, 295529, 263390, 234747, 209218, 186466,
+    166188, 148115, 132008, 117652, 104858, 93454, 83291, 74233,
+    66161, 58966, 52553, 46838, 41745, 37205, 33159, 29553,
+    26339, 23475, 20922, 18647, 16619, 14812, 13201, 11765,
+    10486, 9345, 8329, 7423, 6616, 5897, 5255, 4684,
+    4174, 3720, 3316, 2955, 2634, 2347, 2092, 1865,
+    1662, 1481, 1320, 1177, 1049,
+];
+
+// Converts a volume in 1/256 dB to a Q15 gain.
+// Volumes between two whole dBs are interpolated linearly. 0x8000 is silence (-∞ dB).
+pub fn volume_to_gain(volume: i16, mute: bool) -> i32 {
+    if mute || volume == i16::MIN {
+        return 0;
+    }
+
+    let attenuation = -(volume.clamp(VOLUME_MIN, VOLUME_MAX) as i32);
+    let index = (attenuation >> 8) as usize;
+    let fraction = attenuation & 0xff;
+    let a = ATTENUATION_TO_GAIN[index];
+    let b = ATTENUATION_TO_GAIN[(index + 1).min(ATTENUATION_TO_GAIN.len() - 1)];
+    let gain = a + (((b - a) * fraction) >> 8);
+    (gain + (1 << 4)) >> 5
+}
+
+// Follows the target gain with a one pole low-pass so that changes do not cause zipper noise.
+// The time constant is 256 samples (6.4ms at 40kHz).
+pub struct SmoothedGain {
+    target: i32,
+    // Q30
+    current: i32,
+}
+
+impl SmoothedGain {
+    const SMOOTHING_SHIFT: u32 = 8;
+
+    pub const fn new(gain: i32) -> Self {
+        Self {
+            target: gain,
+            current: gain << 15,
+        }
+    }
+
+    pub fn set_target(&mut self, gain: i32) {
+        self.target = gain.clamp(0, UNITY_GAIN);
+    }
+
+    pub fn process(&mut self, x: i16) -> i16 {
+        self.current += ((self.target << 15) - self.current) >> Self::SMOOTHING_SHIFT;
+        ((x as i64 * self.current as i64) >> 30) as i16
+    }
+}
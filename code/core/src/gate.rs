@@ -0,0 +1,102 @@
+// Noise gate that turns the carrier off when a source is idle
+//
+// The level is the RMS of the source over RMS_TIME. The gate opens above the open threshold
+// and closes after the level has stayed below the lower close threshold for the hold time.
+// The carrier ramps in and out over the fade time instead of switching, so that opening and
+// closing is inaudible.
+
+use crate::filter::{self, OnePole};
+
+// µs
+const RMS_TIME: u32 = 10_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct GateSettings {
+    // Tenths of a dB relative to full scale
+    pub open: i32,
+    pub close: i32,
+    // ms
+    pub hold: i32,
+    pub fade: i32,
+}
+
+impl GateSettings {
+    pub const DEFAULT: Self = Self {
+        open: -600,
+        close: -660,
+        hold: 500,
+        fade: 20,
+    };
+}
+
+// Converts a level in tenths of a dB relative to full scale to a mean square
+fn mean_square(level: i32) -> i32 {
+    let amplitude = libm::powf(10.0, level as f32 / 200.0) * i16::MAX as f32;
+    (amplitude * amplitude) as i32
+}
+
+pub struct Gate {
+    mean_square: OnePole,
+    open_level: i32,
+    close_level: i32,
+    // Samples
+    hold: u32,
+    hold_left: u32,
+    open: bool,
+    // Samples
+    fade: u32,
+}
+
+impl Gate {
+    pub const fn new() -> Self {
+        Self {
+            mean_square: OnePole::new(),
+            open_level: 0,
+            close_level: 0,
+            hold: 0,
+            hold_left: 0,
+            open: false,
+            fade: 1,
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: &GateSettings, sample_rate: u32) {
+        self.mean_square
+            .set_coefficient(filter::time_constant(RMS_TIME, sample_rate));
+        self.open_level = mean_square(settings.open);
+        // The close threshold is never above the open one
+        self.close_level = mean_square(settings.close).min(self.open_level);
+        self.hold = settings.hold as u32 * sample_rate / 1000;
+        self.fade = (settings.fade as u32 * sample_rate / 1000).max(1);
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    // Samples over which the carrier ramps when the gate opens or closes
+    pub fn fade(&self) -> u32 {
+        self.fade
+    }
+
+    pub fn process(&mut self, x: i16) {
+        let level = self.mean_square.process(x as i32 * x as i32);
+
+        if level > self.open_level {
+            self.open = true;
+        }
+        if level >= self.close_level {
+            self.hold_left = self.hold;
+        } else if self.hold_left > 0 {
+            self.hold_left -= 1;
+        } else {
+            self.open = false;
+        }
+    }
+}
+
+impl Default for Gate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
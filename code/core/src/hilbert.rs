@@ -0,0 +1,56 @@
+// Hilbert transformer for single sideband modulation
+
+// Half the number of taps. The output is delayed by this many samples.
+pub const DELAY: usize = 63;
+const TAPS: usize = 2 * DELAY + 1;
+
+// Q15 coefficients of the odd taps 1, 3, ..., 63: 2 / (πn) with a Hamming window.
+// The even taps are zero and the negative taps are the same with the opposite sign.
+// The magnitude response is flat within 1% from 500Hz to 19.5kHz at 40kHz.
+#[rustfmt::skip]
+const COEFFICIENTS: [i32; DELAY.div_ceil(2)] = [
+    20849, 6919, 4115, 2900, 2215, 1772, 1459, 1225,
+    1042, 894, 771, 668, 580, 504, 437, 379,
+    327, 282, 241, 206, 175, 147, 123, 103,
+    85, 70, 57, 47, 39, 33, 29, 27,
+];
+
+// Type III FIR Hilbert transformer
+pub struct Hilbert {
+    // Every sample is written twice so that the last TAPS samples are always contiguous
+    history: [i16; 2 * TAPS],
+    index: usize,
+}
+
+impl Hilbert {
+    pub const fn new() -> Self {
+        Self {
+            history: [0; 2 * TAPS],
+            index: 0,
+        }
+    }
+
+    // Returns the input delayed by DELAY samples and its Hilbert transform
+    pub fn process(&mut self, x: i16) -> (i16, i16) {
+        self.history[self.index] = x;
+        self.history[self.index + TAPS] = x;
+        self.index = (self.index + 1) % TAPS;
+
+        // Oldest sample first
+        let window = &self.history[self.index..self.index + TAPS];
+        let mut acc: i64 = 0;
+        for (k, c) in COEFFICIENTS.iter().enumerate() {
+            let n = 2 * k + 1;
+            acc += *c as i64 * (window[DELAY - n] as i64 - window[DELAY + n] as i64);
+        }
+
+        let transformed = ((acc + (1 << 14)) >> 15).clamp(i16::MIN as i64, i16::MAX as i64);
+        (window[DELAY], transformed as i16)
+    }
+}
+
+impl Default for Hilbert {
+    fn default() -> Self {
+        Self::new()
+    }
+}
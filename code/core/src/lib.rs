@@ -0,0 +1,31 @@
+// Everything of the firmware that does not touch the hardware. It builds for the host as well,
+// where it is tested.
+
+#![no_std]
+
+pub mod battery;
+pub mod berktay;
+pub mod carrier;
+pub mod config;
+pub mod control;
+pub mod current;
+pub mod dead_time;
+pub mod dynamics;
+pub mod eq;
+pub mod fault;
+pub mod feedback;
+pub mod filter;
+pub mod gain;
+pub mod gate;
+pub mod hilbert;
+pub mod limiter;
+pub mod modulation;
+pub mod pcm1808;
+pub mod pipeline;
+pub mod resample;
+pub mod reset;
+pub mod servo;
+pub mod source;
+pub mod telemetry;
+pub mod thermal;
+pub mod usb_audio;
@@ -0,0 +1,112 @@
+// Look-ahead peak limiter that keeps the carrier from being overmodulated
+//
+// The audio is delayed by LOOKAHEAD samples, so the gain is already reduced when a peak arrives.
+// Whatever is left above the threshold after the attack is clipped.
+
+use crate::filter::{self, ONE};
+use crate::gain::UNITY_GAIN;
+
+// 2ms at 40kHz
+pub const LOOKAHEAD: usize = 80;
+
+// The peak of a sample has to be known until the sample leaves the delay line
+const WINDOW: usize = LOOKAHEAD + 1;
+
+// Converts tenths of a dB to a Q15 gain
+pub fn gain_from_db(db: i32) -> i32 {
+    (libm::powf(10.0, db as f32 / 200.0) * UNITY_GAIN as f32) as i32
+}
+
+// Converts a Q15 gain to tenths of a dB
+pub fn gain_to_db(gain: i32) -> i32 {
+    (200.0 * libm::log10f(gain.max(1) as f32 / UNITY_GAIN as f32)) as i32
+}
+
+pub struct Limiter {
+    threshold: i32,
+    // Q24
+    attack: i64,
+    release: i64,
+    // Q30
+    gain: i32,
+    delay: [i16; LOOKAHEAD],
+    time: u32,
+    // Ascending times and descending levels of the samples that can still be the peak of the
+    // look-ahead window. The first one is the peak.
+    peaks: [(u32, i32); WINDOW],
+    first: usize,
+    len: usize,
+}
+
+impl Limiter {
+    pub const fn new() -> Self {
+        Self {
+            threshold: i16::MAX as i32,
+            attack: ONE,
+            release: ONE,
+            gain: UNITY_GAIN << 15,
+            delay: [0; LOOKAHEAD],
+            time: 0,
+            peaks: [(0, 0); WINDOW],
+            first: 0,
+            len: 0,
+        }
+    }
+
+    // The threshold is in tenths of a dB relative to full scale, attack in µs and release in ms
+    pub fn set_parameters(&mut self, threshold: i32, attack: u32, release: u32, sample_rate: u32) {
+        self.threshold = (gain_from_db(threshold) * i16::MAX as i32) >> 15;
+        self.attack = filter::time_constant(attack, sample_rate);
+        self.release = filter::time_constant(release * 1000, sample_rate);
+    }
+
+    // Q15 gain applied to the current output sample
+    pub fn gain(&self) -> i32 {
+        self.gain >> 15
+    }
+
+    pub fn process(&mut self, x: i16) -> i16 {
+        self.push_peak((x as i32).abs());
+
+        let peak = self.peaks[self.first].1;
+        let target = if peak > self.threshold {
+            (self.threshold << 15) / peak
+        } else {
+            UNITY_GAIN
+        };
+
+        let k = if target < self.gain() {
+            self.attack
+        } else {
+            self.release
+        };
+        self.gain += (((((target as i64) << 15) - self.gain as i64) * k) >> 24) as i32;
+
+        let index = self.time as usize % LOOKAHEAD;
+        let delayed = core::mem::replace(&mut self.delay[index], x);
+        self.time = self.time.wrapping_add(1);
+
+        let y = (delayed as i32 * self.gain()) >> 15;
+        y.clamp(-self.threshold, self.threshold) as i16
+    }
+
+    fn push_peak(&mut self, level: i32) {
+        // Drop the peak that has left the window
+        if self.len > 0 && self.time.wrapping_sub(self.peaks[self.first].0) >= WINDOW as u32 {
+            self.first = (self.first + 1) % WINDOW;
+            self.len -= 1;
+        }
+        // Drop the samples that can no longer be the peak
+        while self.len > 0 && self.peaks[(self.first + self.len - 1) % WINDOW].1 <= level {
+            self.len -= 1;
+        }
+        self.peaks[(self.first + self.len) % WINDOW] = (self.time, level);
+        self.len += 1;
+    }
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,144 @@
+// Envelope of the carrier as a function of the audio sample
+//
+// The audible sound demodulated by the air is roughly proportional to the square of the envelope.
+// Plain double sideband AM therefore produces a strong second harmonic. Taking the square root
+// of the DSB envelope before modulating cancels this out.
+//
+// Single sideband modulation only uses half of the narrow transducer bandwidth for every
+// frequency. It needs both the amplitude and the phase of the carrier.
+
+use crate::gain::UNITY_GAIN;
+use crate::hilbert::Hilbert;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Modulation {
+    // Double sideband AM, the envelope is (1 + m·x) / 2
+    Dsb,
+    // Square-root AM, the envelope is sqrt((1 + m·x) / 2)
+    Sram,
+    // Carrier and upper sideband, delayed by hilbert::DELAY samples
+    UpperSsb,
+    // Carrier and lower sideband, delayed by hilbert::DELAY samples
+    LowerSsb,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Envelope {
+    // Q15, from 0 to UNITY_GAIN
+    pub amplitude: i32,
+    // Phase of the carrier, a full turn is 2^32
+    pub phase: i32,
+}
+
+pub struct Modulator {
+    modulation: Modulation,
+    // Modulation index, Q15
+    index: i32,
+    hilbert: Hilbert,
+}
+
+impl Modulator {
+    pub const fn new(modulation: Modulation, index: i32) -> Self {
+        Self {
+            modulation,
+            index,
+            hilbert: Hilbert::new(),
+        }
+    }
+
+    pub fn set_modulation(&mut self, modulation: Modulation) {
+        self.modulation = modulation;
+    }
+
+    pub fn set_index(&mut self, index: i32) {
+        self.index = index.clamp(0, UNITY_GAIN);
+    }
+
+    // Returns the envelope for the sample `x`
+    pub fn modulate(&mut self, x: i16) -> Envelope {
+        let amplitude = match self.modulation {
+            Modulation::Dsb => self.dsb(x) >> 15,
+            Modulation::Sram => isqrt(self.dsb(x) as u32) as i32,
+            Modulation::UpperSsb | Modulation::LowerSsb => return self.ssb(x),
+        };
+        Envelope {
+            amplitude,
+            phase: 0,
+        }
+    }
+
+    // (1 + m·x) / 2 in Q30
+    fn dsb(&self, x: i16) -> i32 {
+        (((UNITY_GAIN << 15) + self.index * x as i32) >> 1).max(0)
+    }
+
+    // (1 + m·x)·cos(ωt) ∓ m·H(x)·sin(ωt), scaled by 1/2 like DSB
+    fn ssb(&mut self, x: i16) -> Envelope {
+        let (x, transformed) = self.hilbert.process(x);
+        let in_phase = (UNITY_GAIN + ((self.index * x as i32) >> 15)) >> 1;
+        let mut quadrature = (self.index * transformed as i32) >> 16;
+        if self.modulation == Modulation::LowerSsb {
+            quadrature = -quadrature;
+        }
+
+        let power = (in_phase * in_phase) as u32 + (quadrature * quadrature) as u32;
+        Envelope {
+            amplitude: (isqrt(power) as i32).min(UNITY_GAIN),
+            phase: idsp::atan2(quadrature, in_phase),
+        }
+    }
+}
+
+// Moves the phase of the carrier by changing the length of single carrier periods
+pub struct PhaseShifter {
+    // Nominal carrier period in timer ticks
+    period: u32,
+    // A full turn is 2^32
+    phase: i32,
+}
+
+impl PhaseShifter {
+    // An eighth of a turn, so that a period is never more than 12.5% shorter or longer
+    const MAX_STEP: i32 = 1 << 29;
+
+    pub const fn new(period: u32) -> Self {
+        Self { period, phase: 0 }
+    }
+
+    // Returns the length of the next carrier period in timer ticks.
+    // Large phase jumps are spread over multiple periods.
+    pub fn update(&mut self, phase: i32) -> u32 {
+        let step = phase
+            .wrapping_sub(self.phase)
+            .clamp(-Self::MAX_STEP, Self::MAX_STEP);
+        self.phase = self.phase.wrapping_add(step);
+        // Advancing the phase makes the period shorter
+        (self.period as i64 - ((self.period as i64 * step as i64) >> 32)) as u32
+    }
+}
+
+// Converts the modulation index in percent to Q15
+pub fn index_from_percent(percent: i32) -> i32 {
+    (percent.clamp(0, 100) * UNITY_GAIN) / 100
+}
+
+// Integer square root rounded down
+pub fn isqrt(x: u32) -> u32 {
+    let mut remainder = x;
+    let mut root = 0;
+    let mut bit = 1 << 30;
+
+    while bit > remainder {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if remainder >= root + bit {
+            remainder -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
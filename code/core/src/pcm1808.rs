@@ -0,0 +1,26 @@
+// Conversion of the PCM1808 I2S stream into the mono samples consumed by the modulator
+
+// The PCM1808 sends 24-bit samples in 32-bit channels. The SPI data register is 16 bits wide,
+// so every channel arrives as the most significant half-word followed by the least significant one.
+pub const CHANNEL_LEN: usize = 2;
+pub const FRAME_LEN: usize = 2 * CHANNEL_LEN;
+
+// Sign-extend a 24-bit left-aligned sample received as two half-words
+pub fn channel_to_i32(hi: u16, lo: u16) -> i32 {
+    ((((hi as u32) << 16) | lo as u32) as i32) >> 8
+}
+
+// Average both channels of a 24-bit stereo pair and reduce it to 16 bits
+pub fn downmix(left: i32, right: i32) -> i16 {
+    // The sum of two 24-bit values fits into an i32 and the average fits into 24 bits
+    (((left + right) >> 1) >> 8) as i16
+}
+
+// The channel order does not matter because the frame gets downmixed anyway, so a stream that
+// starts on the right channel produces the same samples
+pub fn frame_to_i16(frame: &[u16; FRAME_LEN]) -> i16 {
+    downmix(
+        channel_to_i32(frame[0], frame[1]),
+        channel_to_i32(frame[2], frame[3]),
+    )
+}
@@ -0,0 +1,102 @@
+// Processing of the audio in blocks
+//
+// A processor works on a block of samples in place, and processors are chained into a pipeline
+// that runs each of them over the whole block in turn. The samples are i16 widened to i32, and
+// every processor saturates them back to i16 before it works on them.
+//
+// The resampler and the modulator sit at the ends of a pipeline rather than in it. The resampler
+// produces a different number of samples than it takes, and the modulator turns the samples into
+// envelopes of the carrier. Every source runs through its own pipeline, and the mix of the sources
+// through another one, as the mix takes a block of every source.
+
+use crate::berktay::BerktayEq;
+use crate::dynamics::Dynamics;
+use crate::eq::Equalizer;
+use crate::gain::SmoothedGain;
+use crate::gate::Gate;
+use crate::limiter::Limiter;
+
+pub trait Processor {
+    fn process(&mut self, block: &mut [i32]);
+
+    // Returns a pipeline that runs `next` on the output of this processor
+    fn then<P: Processor>(self, next: P) -> Chain<Self, P>
+    where
+        Self: Sized,
+    {
+        Chain::new(self, next)
+    }
+}
+
+// Lets a pipeline be built from processors that are kept elsewhere
+impl<P: Processor + ?Sized> Processor for &mut P {
+    fn process(&mut self, block: &mut [i32]) {
+        (**self).process(block);
+    }
+}
+
+// Lets a processor be left out of a pipeline, which then passes the block through
+impl<P: Processor> Processor for Option<P> {
+    fn process(&mut self, block: &mut [i32]) {
+        if let Some(processor) = self {
+            processor.process(block);
+        }
+    }
+}
+
+pub struct Chain<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: Processor, B: Processor> Chain<A, B> {
+    pub const fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+
+    pub fn first(&mut self) -> &mut A {
+        &mut self.first
+    }
+
+    pub fn second(&mut self) -> &mut B {
+        &mut self.second
+    }
+}
+
+impl<A: Processor, B: Processor> Processor for Chain<A, B> {
+    fn process(&mut self, block: &mut [i32]) {
+        self.first.process(block);
+        self.second.process(block);
+    }
+}
+
+// Saturates a sample of a block to i16
+pub fn saturate(x: i32) -> i16 {
+    x.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+}
+
+// Processors that turn one sample into another
+macro_rules! sample_processor {
+    ($($processor:ty),*) => {
+        $(
+            impl Processor for $processor {
+                fn process(&mut self, block: &mut [i32]) {
+                    for x in block.iter_mut() {
+                        *x = <$processor>::process(self, saturate(*x)) as i32;
+                    }
+                }
+            }
+        )*
+    };
+}
+
+sample_processor!(SmoothedGain, Equalizer, BerktayEq, Limiter, Dynamics);
+
+// The gate only follows the level of the block, which passes through unchanged
+impl Processor for Gate {
+    fn process(&mut self, block: &mut [i32]) {
+        for &x in block.iter() {
+            Gate::process(self, saturate(x));
+        }
+    }
+}
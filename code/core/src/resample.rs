@@ -0,0 +1,74 @@
+// Sample rate conversion of USB audio to the carrier rate
+
+// Rates advertised to the USB host
+pub const USB_SAMPLE_RATES: [u32; 3] = [32000, 44100, 48000];
+
+const ONE: u64 = 1 << 32;
+
+// Cubic Lagrange interpolator in Farrow structure.
+// Everything is done in integer arithmetic, so the output is bit-exact on every target.
+pub struct Resampler {
+    // Input samples per output sample, Q32.32
+    nominal_step: u64,
+    step: u64,
+    // Position of the next output sample after history[1], Q32.32
+    phase: u64,
+    history: [i16; 4],
+}
+
+impl Resampler {
+    pub const fn new(input_rate: u32, output_rate: u32) -> Self {
+        Self {
+            nominal_step: Self::step(input_rate, output_rate),
+            step: Self::step(input_rate, output_rate),
+            phase: 0,
+            history: [0; 4],
+        }
+    }
+
+    const fn step(input_rate: u32, output_rate: u32) -> u64 {
+        ((input_rate as u64) << 32) / output_rate as u64
+    }
+
+    pub fn set_rates(&mut self, input_rate: u32, output_rate: u32) {
+        self.nominal_step = Self::step(input_rate, output_rate);
+        self.step = self.nominal_step;
+    }
+
+    // Adjusts the output rate to compensate for clock drift between the input and the output.
+    // `correction` is relative in Q32, positive values produce more output samples.
+    pub fn set_correction(&mut self, correction: i64) {
+        let nominal_step = self.nominal_step as i64;
+        self.step = (nominal_step - ((nominal_step * correction) >> 32)) as u64;
+    }
+
+    // Feeds one input sample and calls `output` for every output sample that becomes available
+    pub fn process(&mut self, input: i16, mut output: impl FnMut(i16)) {
+        self.history = [self.history[1], self.history[2], self.history[3], input];
+        while self.phase < ONE {
+            output(interpolate(&self.history, (self.phase >> 17) as i64));
+            self.phase += self.step;
+        }
+        self.phase -= ONE;
+    }
+}
+
+// Interpolates between x[1] and x[2] at `mu` (Q15).
+// The polynomial coefficients are scaled by 6 so that they stay integers.
+pub fn interpolate(x: &[i16; 4], mu: i64) -> i16 {
+    let [x0, x1, x2, x3] = x.map(|x| x as i64);
+
+    let c0 = 6 * x1;
+    let c1 = 6 * x2 - 2 * x0 - 3 * x1 - x3;
+    let c2 = 3 * x0 - 6 * x1 + 3 * x2;
+    let c3 = x3 - x0 + 3 * x1 - 3 * x2;
+
+    // Horner's scheme in Q15
+    let mut acc = c3 * mu;
+    acc = ((acc + (c2 << 15)) * mu) >> 15;
+    acc = ((acc + (c1 << 15)) * mu) >> 15;
+    acc += c0 << 15;
+
+    let y = (acc + (3 << 15)).div_euclid(6 << 15);
+    y.clamp(i16::MIN as i64, i16::MAX as i64) as i16
+}
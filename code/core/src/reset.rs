@@ -0,0 +1,121 @@
+// Reason for the last reset
+//
+// The reset flags of the RCC only tell that the watchdog has fired, not why. The reason is
+// therefore also recorded in RAM that is not initialised on boot, right before the firmware
+// gives up.
+
+use core::mem::MaybeUninit;
+use core::ptr::{addr_of, addr_of_mut};
+
+// Marks a valid record. RAM comes up with random contents after a power-on.
+const MAGIC: u32 = 0x5e5e_7a11;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+#[repr(u32)]
+pub enum ResetReason {
+    PowerOn = 0,
+    Brownout = 1,
+    // NRST
+    Pin = 2,
+    Software = 3,
+    // An interrupt handler never returned
+    Watchdog = 4,
+    // The PWM buffer stopped being filled
+    AudioStalled = 5,
+    // OTG_FS stopped running
+    UsbStalled = 6,
+    Panic = 7,
+    HardFault = 8,
+}
+
+impl TryFrom<u32> for ResetReason {
+    type Error = ();
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ResetReason::PowerOn),
+            1 => Ok(ResetReason::Brownout),
+            2 => Ok(ResetReason::Pin),
+            3 => Ok(ResetReason::Software),
+            4 => Ok(ResetReason::Watchdog),
+            5 => Ok(ResetReason::AudioStalled),
+            6 => Ok(ResetReason::UsbStalled),
+            7 => Ok(ResetReason::Panic),
+            8 => Ok(ResetReason::HardFault),
+            _ => Err(()),
+        }
+    }
+}
+
+// Reset flags of RCC_CSR. Every internal reset also pulls NRST low, so the pin flag does not
+// tell anything on its own.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ResetFlags {
+    pub power_on: bool,
+    pub brownout: bool,
+    pub software: bool,
+    pub watchdog: bool,
+}
+
+// A power-on or a brownout means that the record is left over from before the power was lost.
+// Without any other flag the reset came from the pin.
+pub fn reset_reason(flags: ResetFlags, recorded: Option<ResetReason>) -> ResetReason {
+    if flags.power_on {
+        ResetReason::PowerOn
+    } else if flags.brownout {
+        ResetReason::Brownout
+    } else if let Some(reason) = recorded {
+        reason
+    } else if flags.watchdog {
+        ResetReason::Watchdog
+    } else if flags.software {
+        ResetReason::Software
+    } else {
+        ResetReason::Pin
+    }
+}
+
+// Lives in a `.uninit` section, so its words are uninitialised until the record is first set. The
+// reason is only read once the magic number shows that it has been written. The words are accessed
+// volatile, because the writes are only observed after the reset.
+#[repr(C)]
+pub struct ResetRecord {
+    magic: MaybeUninit<u32>,
+    reason: MaybeUninit<u32>,
+}
+
+impl ResetRecord {
+    // Leaves the RAM of the record as it is
+    pub const fn uninit() -> Self {
+        Self {
+            magic: MaybeUninit::uninit(),
+            reason: MaybeUninit::uninit(),
+        }
+    }
+
+    pub fn is_set(&self) -> bool {
+        // The RAM holds some word even before it has been written, which is only compared
+        let magic = unsafe { addr_of!(self.magic).read_volatile() };
+        unsafe { magic.assume_init() == MAGIC }
+    }
+
+    pub fn set(&mut self, reason: ResetReason) {
+        unsafe {
+            addr_of_mut!(self.reason).write_volatile(MaybeUninit::new(reason as u32));
+            addr_of_mut!(self.magic).write_volatile(MaybeUninit::new(MAGIC));
+        }
+    }
+
+    // Returns the recorded reason and clears the record
+    pub fn take(&mut self) -> Option<ResetReason> {
+        let reason = if self.is_set() {
+            // Written before the magic number
+            let reason = unsafe { addr_of!(self.reason).read_volatile().assume_init() };
+            ResetReason::try_from(reason).ok()
+        } else {
+            None
+        };
+        unsafe { addr_of_mut!(self.magic).write_volatile(MaybeUninit::new(0)) };
+        reason
+    }
+}
@@ -0,0 +1,71 @@
+// PI loop that keeps a sample queue at a target fill level.
+// The output is a relative rate correction in Q32 (1 << 32 is 100%). Positive values mean that
+// the queue needs more samples.
+
+// Half a queue of 4096 samples of error asks for 0.5% more samples
+const KP_DIV: i64 = 2048 * 200;
+// Critically damped for the roughly 40 to 64 samples that move through the queue per update
+const KI_DIV: i64 = KP_DIV * 16384;
+// The correction is limited to 1%
+const MAX_CORRECTION_DIV: i64 = 100;
+
+pub const MAX_CORRECTION: i64 = (1 << 32) / MAX_CORRECTION_DIV;
+
+pub struct FillServo {
+    target: i32,
+    // Low-pass filtered fill level, Q8
+    level: i32,
+    // Accumulated error, Q8
+    integral: i64,
+}
+
+impl FillServo {
+    pub const fn new(target: usize) -> Self {
+        Self {
+            target: target as i32,
+            level: (target as i32) << 8,
+            integral: 0,
+        }
+    }
+
+    // Called after every write to the queue with its fill level
+    pub fn update(&mut self, fill: usize) -> i64 {
+        // The fill level jumps by a whole packet or block every update, so it gets smoothed first
+        self.level += (((fill as i32) << 8) - self.level) >> 4;
+        let error = ((self.target << 8) - self.level) as i64;
+
+        let integral_limit = (KI_DIV << 8) / MAX_CORRECTION_DIV;
+        self.integral = (self.integral + error).clamp(-integral_limit, integral_limit);
+
+        let correction = (error << 24) / KP_DIV + (self.integral << 24) / KI_DIV;
+        correction.clamp(-MAX_CORRECTION, MAX_CORRECTION)
+    }
+}
+
+// Holds back reading from a queue after it ran empty until it has filled up to the target again,
+// so that a stream starts with the full margin against jitter
+pub struct Priming {
+    target: usize,
+    primed: bool,
+}
+
+impl Priming {
+    pub const fn new(target: usize) -> Self {
+        Self {
+            target,
+            primed: false,
+        }
+    }
+
+    // Called before every read with the fill level
+    pub fn is_primed(&mut self, fill: usize) -> bool {
+        if fill >= self.target {
+            self.primed = true;
+        }
+        self.primed
+    }
+
+    pub fn underrun(&mut self) {
+        self.primed = false;
+    }
+}
@@ -0,0 +1,159 @@
+// Selection of the audio source that feeds the modulator
+
+use crate::gain::UNITY_GAIN;
+
+// 10ms at 40kHz
+pub const CROSSFADE_SAMPLES: i32 = 400;
+// A stream is considered stopped when no packet has arrived for 5ms (USB sends one every 1ms)
+pub const STREAM_TIMEOUT_SAMPLES: u32 = 200;
+// The jack switch has to be stable for 20ms
+pub const JACK_DEBOUNCE_SAMPLES: u16 = 800;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Source {
+    Usb,
+    Analog,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum SourceMode {
+    // USB when it is enumerated and streaming, otherwise the analog jack when a plug is inserted
+    Auto,
+    ForceUsb,
+    ForceAnalog,
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SourceStatus {
+    pub usb_configured: bool,
+    pub usb_streaming: bool,
+    pub jack_plugged: bool,
+}
+
+pub fn select(mode: SourceMode, status: SourceStatus) -> Option<Source> {
+    match mode {
+        SourceMode::ForceUsb => Some(Source::Usb),
+        SourceMode::ForceAnalog => Some(Source::Analog),
+        SourceMode::Auto if status.usb_configured && status.usb_streaming => Some(Source::Usb),
+        SourceMode::Auto if status.jack_plugged => Some(Source::Analog),
+        SourceMode::Auto => None,
+    }
+}
+
+// Considers a stream active while its packet counter keeps changing
+pub struct StreamMonitor {
+    last_count: u32,
+    idle_samples: u32,
+}
+
+impl StreamMonitor {
+    pub const fn new() -> Self {
+        Self {
+            last_count: 0,
+            idle_samples: STREAM_TIMEOUT_SAMPLES,
+        }
+    }
+
+    // Called once per sample with the current packet count
+    pub fn update(&mut self, count: u32) -> bool {
+        if count != self.last_count {
+            self.last_count = count;
+            self.idle_samples = 0;
+        } else {
+            self.idle_samples = self.idle_samples.saturating_add(1);
+        }
+        self.idle_samples < STREAM_TIMEOUT_SAMPLES
+    }
+}
+
+impl Default for StreamMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Debouncer {
+    state: bool,
+    count: u16,
+}
+
+impl Debouncer {
+    pub const fn new(state: bool) -> Self {
+        Self { state, count: 0 }
+    }
+
+    pub fn update(&mut self, input: bool) -> bool {
+        if input == self.state {
+            self.count = 0;
+        } else {
+            self.count += 1;
+            if self.count >= JACK_DEBOUNCE_SAMPLES {
+                self.state = input;
+                self.count = 0;
+            }
+        }
+        self.state
+    }
+}
+
+// Switches between the sources with a linear crossfade
+pub struct Arbiter {
+    mode: SourceMode,
+    active: Option<Source>,
+    // Q15
+    usb_gain: i32,
+    analog_gain: i32,
+}
+
+impl Arbiter {
+    pub const fn new(mode: SourceMode) -> Self {
+        Self {
+            mode,
+            active: None,
+            usb_gain: 0,
+            analog_gain: 0,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: SourceMode) {
+        self.mode = mode;
+    }
+
+    pub fn active(&self) -> Option<Source> {
+        self.active
+    }
+
+    // Whether the source currently contributes to the output, including while it fades out
+    pub fn is_audible(&self, source: Source) -> bool {
+        match source {
+            Source::Usb => self.usb_gain > 0,
+            Source::Analog => self.analog_gain > 0,
+        }
+    }
+
+    // Returns true when the active source has changed
+    pub fn update(&mut self, status: SourceStatus) -> bool {
+        let active = select(self.mode, status);
+        let changed = active != self.active;
+        self.active = active;
+        changed
+    }
+
+    // Called once per sample with the next sample of every source
+    pub fn mix(&mut self, usb: i16, analog: i16) -> i16 {
+        const STEP: i32 = UNITY_GAIN / CROSSFADE_SAMPLES;
+
+        let fade = |gain: i32, target: bool| {
+            if target {
+                (gain + STEP).min(UNITY_GAIN)
+            } else {
+                (gain - STEP).max(0)
+            }
+        };
+        self.usb_gain = fade(self.usb_gain, self.active == Some(Source::Usb));
+        self.analog_gain = fade(self.analog_gain, self.active == Some(Source::Analog));
+
+        let mixed = (usb as i32 * self.usb_gain + analog as i32 * self.analog_gain) >> 15;
+        mixed.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+    }
+}
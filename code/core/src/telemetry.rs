@@ -0,0 +1,80 @@
+// Measurements that the host can read over the vendor specific USB interface
+
+use crate::config::ConfigError;
+
+// Identifiers of the measurements as used by the USB control requests
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+#[repr(u16)]
+pub enum Measurement {
+    // Tenths of a dB
+    GainReduction = 0,
+    // mV
+    BatteryVoltage = 1,
+    // Percent
+    StateOfCharge = 2,
+    // Tenths of a °C, i32::MIN without an NTC
+    NtcTemperature = 3,
+    // Tenths of a °C
+    InternalTemperature = 4,
+    // mA, averaged
+    Current = 5,
+    // Faults since the last reset
+    FaultCount = 6,
+    // Bits of the faults that are active, see Fault::bit
+    Faults = 7,
+    // ResetReason of the last reset
+    ResetReason = 8,
+}
+
+impl TryFrom<u16> for Measurement {
+    type Error = ConfigError;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Measurement::GainReduction),
+            1 => Ok(Measurement::BatteryVoltage),
+            2 => Ok(Measurement::StateOfCharge),
+            3 => Ok(Measurement::NtcTemperature),
+            4 => Ok(Measurement::InternalTemperature),
+            5 => Ok(Measurement::Current),
+            6 => Ok(Measurement::FaultCount),
+            7 => Ok(Measurement::Faults),
+            8 => Ok(Measurement::ResetReason),
+            _ => Err(ConfigError::UnknownParam),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Telemetry {
+    // Tenths of a dB, by the limiter
+    pub gain_reduction: i32,
+    // mV
+    pub battery_voltage: i32,
+    // Percent
+    pub state_of_charge: i32,
+    // Tenths of a °C
+    pub ntc_temperature: i32,
+    pub internal_temperature: i32,
+    // mA
+    pub current: i32,
+    pub fault_count: i32,
+    pub faults: i32,
+    pub reset_reason: i32,
+}
+
+impl Telemetry {
+    pub fn get(&self, measurement: Measurement) -> i32 {
+        match measurement {
+            Measurement::GainReduction => self.gain_reduction,
+            Measurement::BatteryVoltage => self.battery_voltage,
+            Measurement::StateOfCharge => self.state_of_charge,
+            Measurement::NtcTemperature => self.ntc_temperature,
+            Measurement::InternalTemperature => self.internal_temperature,
+            Measurement::Current => self.current,
+            Measurement::FaultCount => self.fault_count,
+            Measurement::Faults => self.faults,
+            Measurement::ResetReason => self.reset_reason,
+        }
+    }
+}
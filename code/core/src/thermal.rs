@@ -0,0 +1,135 @@
+// Temperature of the H-bridge and the microcontroller, and thermal derating
+//
+// The NTC sits on the heatsink of the MOSFETs. The output is derated linearly from the derating
+// temperature up to the trip temperature, where the carrier is switched off. It comes back once
+// the temperature has fallen by the hysteresis.
+
+use crate::filter::{self, OnePole};
+use crate::gain::UNITY_GAIN;
+
+// 10k NTC from the ADC pin to GND with a 10k pull-up to VDDA
+pub const NTC_PULL_UP: f32 = 10_000.0;
+// Steinhart–Hart coefficients of a 10k B3950 NTC, fitted to 32.65k at 0°C, 10k at 25°C and
+// 678Ω at 100°C
+const NTC_A: f32 = 1.130_278_6e-3;
+const NTC_B: f32 = 2.339_482e-4;
+const NTC_C: f32 = 8.830_651e-8;
+// Outside of this range the NTC is open or shorted. Ω.
+const NTC_MIN: f32 = 50.0;
+const NTC_MAX: f32 = 1_000_000.0;
+
+const ADC_FULL_SCALE: u32 = 4095;
+// mV, at which the factory calibration of the internal sensor was measured
+const CALIBRATION_VDDA: u32 = 3300;
+
+// Tenths of a °C
+const HYSTERESIS: i32 = 100;
+// µs
+const FILTER_TIME: u32 = 1_000_000;
+// Q15 gain just below the trip temperature
+const MIN_GAIN: i32 = UNITY_GAIN / 4;
+
+const KELVIN: f32 = 273.15;
+
+// Returns the resistance of the NTC in Ω for a ratiometric ADC sample
+pub fn ntc_resistance(sample: u16) -> f32 {
+    let sample = (sample as u32).min(ADC_FULL_SCALE - 1) as f32;
+    NTC_PULL_UP * sample / (ADC_FULL_SCALE as f32 - sample)
+}
+
+// Returns the temperature in °C for a resistance of the NTC in Ω:
+// 1/T = A + B·ln(R) + C·ln(R)³
+pub fn steinhart_hart(resistance: f32) -> f32 {
+    let ln = libm::logf(resistance);
+    1.0 / (NTC_A + NTC_B * ln + NTC_C * ln * ln * ln) - KELVIN
+}
+
+// Returns the temperature of the NTC in °C, or None if it is open or shorted
+pub fn ntc_temperature(sample: u16) -> Option<f32> {
+    let resistance = ntc_resistance(sample);
+    if (NTC_MIN..=NTC_MAX).contains(&resistance) {
+        Some(steinhart_hart(resistance))
+    } else {
+        None
+    }
+}
+
+// Returns the temperature of the internal sensor in °C for its voltage in mV.
+// `cal30` and `cal110` are the factory calibration samples at 30°C and 110°C.
+pub fn internal_temperature(voltage: u32, cal30: u16, cal110: u16) -> f32 {
+    let sample = (voltage * ADC_FULL_SCALE) as f32 / CALIBRATION_VDDA as f32;
+    30.0 + (sample - cal30 as f32) * 80.0 / (cal110 as f32 - cal30 as f32)
+}
+
+// Returns the Q15 gain for a temperature. It is unity up to `derate` and falls linearly to
+// MIN_GAIN at `trip`. All in tenths of a °C.
+pub fn derating(temperature: i32, derate: i32, trip: i32) -> i32 {
+    if temperature <= derate {
+        return UNITY_GAIN;
+    }
+    let temperature = temperature.min(trip);
+    UNITY_GAIN - (UNITY_GAIN - MIN_GAIN) * (temperature - derate) / (trip - derate).max(1)
+}
+
+pub struct ThermalMonitor {
+    // Tenths of a °C
+    derate: i32,
+    trip: i32,
+    // Thousandths of a °C, for precision at the long time constant
+    temperature: OnePole,
+    primed: bool,
+    tripped: bool,
+}
+
+impl ThermalMonitor {
+    pub const fn new() -> Self {
+        Self {
+            derate: 0,
+            trip: 0,
+            temperature: OnePole::new(),
+            primed: false,
+            tripped: false,
+        }
+    }
+
+    // Temperatures in tenths of a °C. `rate` is the rate in Hz at which `update` is called.
+    pub fn set_limits(&mut self, derate: i32, trip: i32, rate: u32) {
+        self.derate = derate;
+        self.trip = trip;
+        self.temperature
+            .set_coefficient(filter::time_constant(FILTER_TIME, rate));
+    }
+
+    // Takes the temperature in tenths of a °C and returns whether the trip temperature has
+    // been reached
+    pub fn update(&mut self, temperature: i32) -> bool {
+        if !self.primed {
+            self.temperature.reset(temperature * 100);
+            self.primed = true;
+        }
+        self.temperature.process(temperature * 100);
+
+        let temperature = self.temperature();
+        if temperature >= self.trip {
+            self.tripped = true;
+        } else if temperature < self.trip - HYSTERESIS {
+            self.tripped = false;
+        }
+        self.tripped
+    }
+
+    // Filtered temperature in tenths of a °C
+    pub fn temperature(&self) -> i32 {
+        self.temperature.output() / 100
+    }
+
+    pub fn gain(&self) -> i32 {
+        derating(self.temperature(), self.derate, self.trip)
+    }
+}
+
+impl Default for ThermalMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
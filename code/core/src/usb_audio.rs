@@ -0,0 +1,381 @@
+// USB Audio Class 1.0 speaker with an asynchronous isochronous OUT endpoint and an explicit
+// feedback endpoint, so that the host follows the carrier clock instead of its own
+
+use defmt::{info, warn};
+use usb_device::{
+    class_prelude::*,
+    control::{Recipient, Request, RequestType},
+    Result,
+};
+
+use crate::feedback;
+use crate::gain::{VOLUME_MAX, VOLUME_MIN, VOLUME_RES};
+
+const AUDIO: u8 = 0x01;
+const AUDIOCONTROL: u8 = 0x01;
+const AUDIOSTREAMING: u8 = 0x02;
+
+const CS_INTERFACE: u8 = 0x24;
+const CS_ENDPOINT: u8 = 0x25;
+
+const HEADER: u8 = 0x01;
+const INPUT_TERMINAL: u8 = 0x02;
+const OUTPUT_TERMINAL: u8 = 0x03;
+const FEATURE_UNIT: u8 = 0x06;
+const AS_GENERAL: u8 = 0x01;
+const FORMAT_TYPE: u8 = 0x02;
+const EP_GENERAL: u8 = 0x01;
+
+const FORMAT_TYPE_I: u8 = 0x01;
+const PCM: u16 = 0x0001;
+const USB_STREAMING: u16 = 0x0101;
+const SPEAKER: u16 = 0x0301;
+
+const SET_CUR: u8 = 0x01;
+const GET_CUR: u8 = 0x81;
+const GET_MIN: u8 = 0x82;
+const GET_MAX: u8 = 0x83;
+const GET_RES: u8 = 0x84;
+const SAMPLING_FREQ_CONTROL: u8 = 0x01;
+const MUTE_CONTROL: u8 = 0x01;
+const VOLUME_CONTROL: u8 = 0x02;
+// bmaControls bits of the feature unit
+const MASTER_CONTROLS: u8 = (1 << (MUTE_CONTROL - 1)) | (1 << (VOLUME_CONTROL - 1));
+
+const INPUT_TERMINAL_ID: u8 = 1;
+const FEATURE_UNIT_ID: u8 = 2;
+const OUTPUT_TERMINAL_ID: u8 = 3;
+
+// Header, input terminal, feature unit and output terminal
+const AC_DESCRIPTORS_LEN: u16 = 9 + 12 + 9 + 9;
+
+const CHANNELS: u8 = 1;
+const SUBFRAME_SIZE: u8 = 2;
+const BIT_RESOLUTION: u8 = 16;
+
+// The host reads the feedback every 2^FEEDBACK_REFRESH frames
+const FEEDBACK_REFRESH: u8 = 3;
+
+pub struct AudioClass<'a, B: UsbBus> {
+    control_iface: InterfaceNumber,
+    stream_iface: InterfaceNumber,
+    ep_data: EndpointOut<'a, B>,
+    ep_feedback: EndpointIn<'a, B>,
+    rates: &'static [u32],
+    alt_setting: u8,
+    sample_rate: u32,
+    // 10.14 samples per frame. Nominal until the first packet has been received at a new sample
+    // rate or alternate setting.
+    feedback: u32,
+    feedback_read: bool,
+    // 1/256 dB
+    volume: i16,
+    mute: bool,
+}
+
+impl<'a, B: UsbBus> AudioClass<'a, B> {
+    pub fn new(alloc: &'a UsbBusAllocator<B>, rates: &'static [u32]) -> Self {
+        let max_rate = rates
+            .iter()
+            .copied()
+            .max()
+            .expect("At least one rate is required");
+        // Leave room for the extra samples the host sends while following the feedback
+        let max_packet_size = (max_rate / 1000 + 2) as u16 * (CHANNELS * SUBFRAME_SIZE) as u16;
+
+        Self {
+            control_iface: alloc.interface(),
+            stream_iface: alloc.interface(),
+            ep_data: alloc.isochronous(
+                IsochronousSynchronizationType::Asynchronous,
+                IsochronousUsageType::Data,
+                max_packet_size,
+                1,
+            ),
+            ep_feedback: alloc.isochronous(
+                IsochronousSynchronizationType::NoSynchronization,
+                IsochronousUsageType::Feedback,
+                3,
+                1,
+            ),
+            rates,
+            alt_setting: 0,
+            sample_rate: max_rate,
+            feedback: feedback::nominal_feedback(max_rate),
+            feedback_read: false,
+            volume: VOLUME_MAX,
+            mute: false,
+        }
+    }
+
+    pub fn read(&self, data: &mut [u8]) -> Result<usize> {
+        self.ep_data.read(data)
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.alt_setting != 0
+    }
+
+    // The sample rate selected by the host
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Volume in 1/256 dB and mute of the master channel
+    pub fn volume(&self) -> (i16, bool) {
+        (self.volume, self.mute)
+    }
+
+    pub fn set_feedback(&mut self, feedback: u32) {
+        self.feedback = feedback;
+    }
+
+    // Returns whether the host has read the feedback since the last call.
+    // Some hosts ignore the feedback endpoint and have to be compensated for on the device.
+    pub fn take_feedback_read(&mut self) -> bool {
+        core::mem::take(&mut self.feedback_read)
+    }
+
+    fn write_feedback(&self) {
+        // A value that has not been collected by the host yet is left in place
+        self.ep_feedback
+            .write(&self.feedback.to_le_bytes()[..3])
+            .ok();
+    }
+
+    fn is_data_endpoint(&self, req: &Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Endpoint
+            && req.index as u8 == u8::from(self.ep_data.address())
+    }
+
+    fn is_feature_unit(&self, req: &Request) -> bool {
+        req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.index == ((FEATURE_UNIT_ID as u16) << 8) | u8::from(self.control_iface) as u16
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for AudioClass<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        let [total_len_lo, total_len_hi] = AC_DESCRIPTORS_LEN.to_le_bytes();
+        let [streaming_lo, streaming_hi] = USB_STREAMING.to_le_bytes();
+        let [speaker_lo, speaker_hi] = SPEAKER.to_le_bytes();
+        let [pcm_lo, pcm_hi] = PCM.to_le_bytes();
+
+        writer.interface(self.control_iface, AUDIO, AUDIOCONTROL, 0)?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                HEADER,
+                0x00, // bcdADC 1.00
+                0x01,
+                total_len_lo,
+                total_len_hi,
+                1, // bInCollection
+                self.stream_iface.into(),
+            ],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                INPUT_TERMINAL,
+                INPUT_TERMINAL_ID,
+                streaming_lo,
+                streaming_hi,
+                0, // bAssocTerminal
+                CHANNELS,
+                0, // wChannelConfig
+                0,
+                0, // iChannelNames
+                0, // iTerminal
+            ],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                FEATURE_UNIT,
+                FEATURE_UNIT_ID,
+                INPUT_TERMINAL_ID,
+                1, // bControlSize
+                MASTER_CONTROLS,
+                0, // Channel 1 has no controls of its own
+                0, // iFeature
+            ],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                OUTPUT_TERMINAL,
+                OUTPUT_TERMINAL_ID,
+                speaker_lo,
+                speaker_hi,
+                0, // bAssocTerminal
+                FEATURE_UNIT_ID,
+                0, // iTerminal
+            ],
+        )?;
+
+        // Zero bandwidth alternate setting
+        writer.interface_alt(self.stream_iface, 0, AUDIO, AUDIOSTREAMING, 0, None)?;
+
+        writer.interface_alt(self.stream_iface, 1, AUDIO, AUDIOSTREAMING, 0, None)?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                AS_GENERAL,
+                INPUT_TERMINAL_ID,
+                1, // bDelay
+                pcm_lo,
+                pcm_hi,
+            ],
+        )?;
+        writer.write_with(CS_INTERFACE, |buf| {
+            let len = 6 + 3 * self.rates.len();
+            if buf.len() < len {
+                return Err(UsbError::BufferOverflow);
+            }
+            buf[..6].copy_from_slice(&[
+                FORMAT_TYPE,
+                FORMAT_TYPE_I,
+                CHANNELS,
+                SUBFRAME_SIZE,
+                BIT_RESOLUTION,
+                self.rates.len() as u8,
+            ]);
+            for (rate, buf) in self.rates.iter().zip(buf[6..].chunks_exact_mut(3)) {
+                buf.copy_from_slice(&rate.to_le_bytes()[..3]);
+            }
+            Ok(len)
+        })?;
+        let feedback_address: u8 = self.ep_feedback.address().into();
+        writer.endpoint_ex(&self.ep_data, |buf| {
+            buf[..2].copy_from_slice(&[0, feedback_address]); // bRefresh, bSynchAddress
+            Ok(2)
+        })?;
+        writer.write(
+            CS_ENDPOINT,
+            &[
+                EP_GENERAL,
+                SAMPLING_FREQ_CONTROL, // bmAttributes
+                0,                     // bLockDelayUnits
+                0,                     // wLockDelay
+                0,
+            ],
+        )?;
+        writer.endpoint_ex(&self.ep_feedback, |buf| {
+            buf[..2].copy_from_slice(&[FEEDBACK_REFRESH, 0]); // bRefresh, bSynchAddress
+            Ok(2)
+        })?;
+
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.alt_setting = 0;
+    }
+
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.ep_feedback.address() && self.is_streaming() {
+            self.feedback_read = true;
+            self.write_feedback();
+        }
+    }
+
+    fn get_alt_setting(&mut self, interface: InterfaceNumber) -> Option<u8> {
+        if interface == self.control_iface {
+            Some(0)
+        } else if interface == self.stream_iface {
+            Some(self.alt_setting)
+        } else {
+            None
+        }
+    }
+
+    fn set_alt_setting(&mut self, interface: InterfaceNumber, alternative: u8) -> bool {
+        if interface == self.stream_iface && alternative <= 1 {
+            self.alt_setting = alternative;
+            self.feedback = feedback::nominal_feedback(self.sample_rate);
+            if self.is_streaming() {
+                self.write_feedback();
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+        let control = (req.value >> 8) as u8;
+        // Only the master channel has controls
+        let channel = req.value as u8;
+
+        if self.is_data_endpoint(&req) {
+            if req.request == GET_CUR && control == SAMPLING_FREQ_CONTROL {
+                xfer.accept_with(&self.sample_rate.to_le_bytes()[..3]).ok();
+            } else {
+                xfer.reject().ok();
+            }
+        } else if self.is_feature_unit(&req) && channel == 0 {
+            match (control, req.request) {
+                (MUTE_CONTROL, GET_CUR) => xfer.accept_with(&[self.mute as u8]),
+                (VOLUME_CONTROL, GET_CUR) => xfer.accept_with(&self.volume.to_le_bytes()),
+                (VOLUME_CONTROL, GET_MIN) => xfer.accept_with(&VOLUME_MIN.to_le_bytes()),
+                (VOLUME_CONTROL, GET_MAX) => xfer.accept_with(&VOLUME_MAX.to_le_bytes()),
+                (VOLUME_CONTROL, GET_RES) => xfer.accept_with(&VOLUME_RES.to_le_bytes()),
+                _ => xfer.reject(),
+            }
+            .ok();
+        } else if self.is_feature_unit(&req) {
+            xfer.reject().ok();
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+        let control = (req.value >> 8) as u8;
+        let channel = req.value as u8;
+
+        if self.is_feature_unit(&req) {
+            let data = xfer.data();
+            match (control, req.request, data.len()) {
+                (MUTE_CONTROL, SET_CUR, 1) if channel == 0 => {
+                    self.mute = data[0] != 0;
+                    xfer.accept().ok();
+                }
+                (VOLUME_CONTROL, SET_CUR, 2) if channel == 0 => {
+                    self.volume = i16::from_le_bytes([data[0], data[1]]);
+                    xfer.accept().ok();
+                }
+                _ => {
+                    xfer.reject().ok();
+                }
+            }
+            return;
+        }
+
+        if !self.is_data_endpoint(&req) {
+            return;
+        }
+
+        if req.request == SET_CUR
+            && (req.value >> 8) as u8 == SAMPLING_FREQ_CONTROL
+            && xfer.data().len() == 3
+        {
+            let data = xfer.data();
+            let rate = u32::from_le_bytes([data[0], data[1], data[2], 0]);
+            if self.rates.contains(&rate) {
+                info!("USB sample rate: {}", rate);
+                self.sample_rate = rate;
+                self.feedback = feedback::nominal_feedback(rate);
+                xfer.accept().ok();
+            } else {
+                warn!("Unsupported sample rate: {}", rate);
+                xfer.reject().ok();
+            }
+        } else {
+            xfer.reject().ok();
+        }
+    }
+}
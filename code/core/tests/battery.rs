@@ -0,0 +1,86 @@
+use parametric_speaker_core::battery::*;
+use parametric_speaker_core::carrier::level;
+
+// Feeds a voltage for 10s at 100Hz
+fn settle(monitor: &mut BatteryMonitor, voltage: u32) -> BatteryState {
+    let mut state = BatteryState::Normal;
+    for _ in 0..1000 {
+        state = monitor.update(voltage);
+    }
+    state
+}
+
+#[test]
+fn divider() {
+    assert_eq!(battery_voltage(1000), 11000);
+}
+
+#[test]
+fn filter_starts_at_the_first_measurement() {
+    let mut monitor = BatteryMonitor::new();
+    monitor.set_chemistry(Chemistry::LiIon3S, 100);
+    assert_eq!(monitor.update(12000), BatteryState::Normal);
+    assert_eq!(monitor.voltage(), 12000);
+    assert_eq!(monitor.gain(), 32768);
+    assert_eq!(monitor.state_of_charge(), 78);
+}
+
+#[test]
+fn li_ion_thresholds_with_hysteresis() {
+    let mut monitor = BatteryMonitor::new();
+    monitor.set_chemistry(Chemistry::LiIon3S, 100);
+    monitor.update(12000);
+
+    // 3.4V per cell
+    assert_eq!(settle(&mut monitor, 10200), BatteryState::Low);
+    assert!((monitor.voltage() as i32 - 10200).abs() <= 2);
+    let gain = monitor.gain();
+    assert!(gain > 8192 && gain < 32768, "{}", gain);
+
+    // A short sag under load does not cut off
+    for _ in 0..5 {
+        assert_eq!(monitor.update(8000), BatteryState::Low);
+    }
+
+    // 3.1V per cell
+    assert_eq!(settle(&mut monitor, 9300), BatteryState::Cutoff);
+    assert_eq!(monitor.gain(), 8192);
+
+    // 3.3V per cell is still within the hysteresis of the cutoff
+    assert_eq!(settle(&mut monitor, 9900), BatteryState::Cutoff);
+    assert_eq!(settle(&mut monitor, 10200), BatteryState::Low);
+    // 3.6V per cell is still within the hysteresis of the warning
+    assert_eq!(settle(&mut monitor, 10800), BatteryState::Low);
+    assert_eq!(settle(&mut monitor, 11100), BatteryState::Normal);
+}
+
+#[test]
+fn off_never_cuts_off() {
+    let mut monitor = BatteryMonitor::new();
+    monitor.set_chemistry(Chemistry::Off, 100);
+    assert_eq!(settle(&mut monitor, 5000), BatteryState::Normal);
+    assert_eq!(monitor.voltage(), 5000);
+    assert_eq!(monitor.gain(), 32768);
+    assert_eq!(monitor.state_of_charge(), 0);
+}
+
+#[test]
+fn lead_acid_state_of_charge() {
+    let mut monitor = BatteryMonitor::new();
+    monitor.set_chemistry(Chemistry::LeadAcid, 100);
+    assert_eq!(monitor.update(12700), BatteryState::Normal);
+    assert!(monitor.state_of_charge() >= 99);
+    settle(&mut monitor, 12200);
+    let charge = monitor.state_of_charge();
+    assert!((45..=52).contains(&charge), "{}", charge);
+}
+
+#[test]
+fn low_battery_derates_the_carrier() {
+    let mut monitor = BatteryMonitor::new();
+    monitor.set_chemistry(Chemistry::LiIon3S, 100);
+    // Halfway between the warning and the cutoff of a cell
+    monitor.update(3 * 3350);
+    assert_eq!(monitor.gain(), 20480);
+    assert_eq!(level(32768, &[monitor.gain()]), 20480);
+}
@@ -0,0 +1,39 @@
+use std::f64::consts::PI;
+
+use parametric_speaker_core::berktay::BerktayEq;
+
+// Two integrators above the corner after a first order high-pass
+#[test]
+fn response_is_double_integration() {
+    let mut eq = BerktayEq::new();
+    eq.set_corners(200, 40, 40000);
+    for frequency in [100.0f64, 200.0, 1000.0, 2000.0] {
+        let mut peak = 0;
+        for n in 0..80000 {
+            let x = (30000.0 * (2.0 * PI * frequency * n as f64 / 40000.0).sin()) as i16;
+            let y = eq.process(x);
+            if n > 40000 {
+                peak = peak.max((y as i32).abs());
+            }
+        }
+        let high_pass = (frequency / 40.0) / (1.0 + (frequency / 40.0).powi(2)).sqrt();
+        let low_pass = 1.0 / (1.0 + (frequency / 200.0).powi(2));
+        let expected = 30000.0 * high_pass * low_pass;
+        assert!(
+            (peak as f64 / expected - 1.0).abs() < 0.05,
+            "{} {} {}",
+            frequency,
+            peak,
+            expected
+        );
+    }
+}
+
+#[test]
+fn disabled_without_a_corner() {
+    let mut eq = BerktayEq::new();
+    eq.set_corners(0, 40, 40000);
+    for x in [0, 1234, -32768, 32767] {
+        assert_eq!(eq.process(x), x);
+    }
+}
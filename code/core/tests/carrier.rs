@@ -0,0 +1,132 @@
+use std::f64::consts::{FRAC_PI_2, PI};
+
+use parametric_speaker_core::carrier::*;
+
+#[test]
+fn ramp_takes_the_fade_time() {
+    let mut ramp = Ramp::new();
+    assert!(ramp.is_off());
+    let mut samples = 0;
+    while ramp.update(true, 800) < 32768 {
+        samples += 1;
+    }
+    assert_eq!(samples, 800);
+    let mut samples = 0;
+    while !ramp.is_off() {
+        ramp.update(false, 80);
+        samples += 1;
+    }
+    assert_eq!(samples, 81);
+}
+
+#[test]
+fn ramp_of_one_sample_switches() {
+    let mut ramp = Ramp::new();
+    assert_eq!(ramp.update(true, 1), 32768);
+    assert_eq!(ramp.update(true, 1), 32768);
+    assert_eq!(ramp.update(false, 1), 0);
+    assert!(ramp.is_off());
+    assert_eq!(ramp.update(true, 0), 32768);
+    assert_eq!(ramp.update(false, 0), 0);
+}
+
+// The fundamental of the bridge voltage is sin(π · duty / period)
+#[test]
+fn duty_sets_the_fundamental() {
+    for period in [2000, 2400, 2800] {
+        for amplitude in (0..=32768).step_by(512) {
+            let duty = duty(amplitude, period);
+            assert!(duty <= period / 2);
+            let fundamental = (PI * duty as f64 / period as f64).sin();
+            assert!(
+                (fundamental - amplitude as f64 / 32768.0).abs() < 0.003,
+                "{} {} {}",
+                period,
+                amplitude,
+                fundamental
+            );
+        }
+    }
+}
+
+// The fundamental of the bridge voltage is cos(π/2 · shift / half period)
+#[test]
+fn leg_compares_set_the_fundamental() {
+    for half_period in [1000, 1200, 1400] {
+        for amplitude in (0..=32768).step_by(512) {
+            let (first, second) = leg_compares(amplitude, half_period);
+            assert!(first <= second && second < half_period);
+            let shift = (second - first) as f64 / half_period as f64;
+            let fundamental = (FRAC_PI_2 * shift).cos();
+            assert!(
+                (fundamental - amplitude as f64 / 32768.0).abs() < 0.003,
+                "{} {} {}",
+                half_period,
+                amplitude,
+                fundamental
+            );
+        }
+    }
+}
+
+#[test]
+fn duty_frames() {
+    assert_eq!(OutputStage::Duty.periods_per_sample(), 1);
+    // A full envelope is a 50% duty, and sin(π/6) is a half
+    assert_eq!(
+        frame(OutputStage::Duty, 2400, 32768, 32768),
+        [2399, 0, 1200, 1200]
+    );
+    assert_eq!(
+        frame(OutputStage::Duty, 2400, 16384, 32768),
+        [2399, 0, 400, 400]
+    );
+    assert_eq!(frame(OutputStage::Duty, 2400, 0, 32768), [2399, 0, 0, 0]);
+    // The ramp scales the amplitude, the carrier is gone at a zero level
+    assert_eq!(
+        frame(OutputStage::Duty, 2400, 32768, 16384),
+        [2399, 0, 400, 400]
+    );
+    assert_eq!(frame(OutputStage::Duty, 2400, 32768, 0), [2399, 0, 0, 0]);
+}
+
+#[test]
+fn phase_shift_frames() {
+    assert_eq!(OutputStage::PhaseShift.periods_per_sample(), 2);
+    // Every timer period is half of the carrier period
+    let [arr, rcr, first, second] = frame(OutputStage::PhaseShift, 2400, 32768, 32768);
+    assert_eq!((arr, rcr), (1199, 0));
+    assert_eq!((first, second), leg_compares(32768, 1200));
+    // The ramp scales the amplitude
+    let [_, _, first, second] = frame(OutputStage::PhaseShift, 2400, 32768, 16384);
+    assert_eq!((first, second), leg_compares(16384, 1200));
+}
+
+// Fundamental of the bridge voltage of a frame, relative to the full carrier
+fn fundamental(stage: OutputStage, [arr, _, first, second]: PwmFrame) -> f64 {
+    let period = arr as f64 + 1.0;
+    match stage {
+        OutputStage::Duty => (PI * first as f64 / period).sin(),
+        OutputStage::PhaseShift => (FRAC_PI_2 * (second - first) as f64 / period).cos(),
+    }
+}
+
+// The gains of the derating scale the fundamental of a full envelope in both stages
+#[test]
+fn level_scales_the_fundamental() {
+    for stage in [OutputStage::Duty, OutputStage::PhaseShift] {
+        for gain in [32768, 24576, 20480, 16384, 8192] {
+            let level = level(32768, &[gain]);
+            let fundamental = fundamental(stage, frame(stage, 2400, 32768, level));
+            assert!(
+                (fundamental - gain as f64 / 32768.0).abs() < 0.003,
+                "{:?} {} {}",
+                stage,
+                gain,
+                fundamental
+            );
+        }
+    }
+    assert_eq!(level(32768, &[16384, 16384]), 8192);
+    assert_eq!(level(16384, &[]), 16384);
+}
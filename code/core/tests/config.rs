@@ -0,0 +1,120 @@
+use parametric_speaker_core::config::*;
+use parametric_speaker_core::control;
+use parametric_speaker_core::source::{Source, SourceMode};
+use parametric_speaker_core::telemetry::{Measurement, Telemetry};
+
+#[test]
+fn params_round_trip_over_the_wire() {
+    let mut config = Config::DEFAULT;
+    let cases: [(u16, i32); 5] = [
+        (12, 500),
+        (0x105, 2000),
+        (0x207, 50),
+        (17, 1500),
+        (0, SourceMode::ForceAnalog as i32),
+    ];
+    for (id, value) in cases {
+        assert_eq!(
+            control::set_param(&mut config, id, &value.to_le_bytes()),
+            Ok(())
+        );
+        assert_eq!(control::get_param(&config, id), Ok(value.to_le_bytes()));
+    }
+    assert_eq!(config.dead_time, 500);
+    assert_eq!(config.eq[1].frequency, 2000);
+    assert_eq!(config.gates[Source::Analog as usize].fade, 50);
+    assert_eq!(config.current_limit, 1500);
+    assert_eq!(config.source_mode, SourceMode::ForceAnalog);
+
+    // Unknown ids and values that are not 4 bytes long are rejected and change nothing
+    let before = config;
+    for id in [18, 0x110, 0x208] {
+        assert_eq!(
+            control::set_param(&mut config, id, &0i32.to_le_bytes()),
+            Err(ConfigError::UnknownParam)
+        );
+        assert_eq!(
+            control::get_param(&config, id),
+            Err(ConfigError::UnknownParam)
+        );
+    }
+    assert_eq!(
+        control::set_param(&mut config, 12, &[0xf4, 0x01]),
+        Err(ConfigError::InvalidValue)
+    );
+    assert_eq!(config, before);
+}
+
+#[test]
+fn every_param_reads_back() {
+    let mut config = Config::DEFAULT;
+    for id in (0..=17).chain(0x100..0x110).chain(0x200..0x208) {
+        let param = Param::try_from(id).unwrap();
+        let value = config.get(param);
+        assert_eq!(config.set(param, value), Ok(()), "{:?}", param);
+        assert_eq!(config, Config::DEFAULT);
+    }
+}
+
+#[test]
+fn rejects_invalid_values() {
+    let mut config = Config::DEFAULT;
+    assert_eq!(
+        config.set(Param::SourceMode, 3),
+        Err(ConfigError::InvalidValue)
+    );
+    assert_eq!(
+        config.set(Param::ModulationIndex, 101),
+        Err(ConfigError::InvalidValue)
+    );
+    assert_eq!(
+        config.set(Param::DeadTime, 100),
+        Err(ConfigError::InvalidValue)
+    );
+    assert_eq!(
+        config.set(Param::Modulation, 4),
+        Err(ConfigError::InvalidValue)
+    );
+    assert_eq!(config, Config::DEFAULT);
+}
+
+#[test]
+fn limits_stay_ordered() {
+    let mut config = Config::DEFAULT;
+    // The derating starts below the trip temperature
+    assert_eq!(
+        config.set(Param::ThermalDerate, config.thermal_trip),
+        Err(ConfigError::InvalidValue)
+    );
+    assert_eq!(
+        config.set(Param::ThermalTrip, config.thermal_derate),
+        Err(ConfigError::InvalidValue)
+    );
+    // The average current is limited below the trip current
+    assert_eq!(
+        config.set(Param::CurrentLimit, config.overcurrent_trip),
+        Err(ConfigError::InvalidValue)
+    );
+    assert_eq!(
+        config.set(Param::OvercurrentTrip, config.current_limit),
+        Err(ConfigError::InvalidValue)
+    );
+    assert_eq!(
+        config.set(Param::OvercurrentTrip, 6001),
+        Err(ConfigError::InvalidValue)
+    );
+    assert_eq!(config.set(Param::OvercurrentTrip, 6000), Ok(()));
+    assert_eq!(config.set(Param::CurrentLimit, 5999), Ok(()));
+}
+
+#[test]
+fn measurement_ids() {
+    let telemetry = Telemetry {
+        battery_voltage: 11100,
+        reset_reason: 5,
+        ..Telemetry::default()
+    };
+    assert_eq!(telemetry.get(Measurement::try_from(1).unwrap()), 11100);
+    assert_eq!(telemetry.get(Measurement::try_from(8).unwrap()), 5);
+    assert_eq!(Measurement::try_from(9), Err(ConfigError::UnknownParam));
+}
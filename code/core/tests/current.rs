@@ -0,0 +1,136 @@
+use parametric_speaker_core::carrier::level;
+use parametric_speaker_core::current::*;
+use parametric_speaker_core::fault::{Fault, Faults};
+
+#[test]
+fn shunt_conversion() {
+    assert_eq!(current(0, 3300), 0);
+    // Full scale
+    assert_eq!(current(4095, 3300), 6600);
+    let trip = sample(5000, 3300);
+    assert!((current(trip, 3300) - 5000).abs() <= 4, "{}", trip);
+    assert_eq!(sample(100_000, 3300), 4095);
+    assert_eq!(sample(-100, 3300), 0);
+}
+
+#[test]
+fn below_the_limit_is_untouched() {
+    let mut limiter = CurrentLimiter::new();
+    limiter.set_limit(2000, 40000);
+    for _ in 0..40000 {
+        assert_eq!(limiter.process(1500), 32768);
+    }
+    assert!(
+        (limiter.average() - 1500).abs() <= 5,
+        "{}",
+        limiter.average()
+    );
+}
+
+// The load draws 4A at full amplitude, in proportion to the gain. The limiter runs once per block
+// of 64 periods at 40kHz.
+#[test]
+fn limits_the_average_current() {
+    let mut limiter = CurrentLimiter::new();
+    limiter.set_limit(2000, 40000 / 64);
+    let mut gain = 32768;
+    let mut averages = vec![];
+    for n in 0..400_000 / 64 {
+        gain = limiter.process(4000 * gain / 32768);
+        if n % 62 == 0 {
+            averages.push(limiter.average());
+        }
+    }
+    assert!((limiter.average() - 2000).abs() < 60, "{:?}", averages);
+    // Hardly overshoots once the limiting has started
+    let overshoot = averages.iter().skip(20).max().unwrap();
+    assert!(*overshoot < 2300, "{:?}", averages);
+    // Halves the carrier
+    assert!((level(32768, &[gain]) - 16384).abs() < 600, "{}", gain);
+
+    // Released once the load draws less
+    for _ in 0..400_000 / 64 {
+        gain = limiter.process(1000 * gain / 32768);
+    }
+    assert!(gain > 32000, "{}", gain);
+}
+
+#[test]
+fn sample_sum_takes_the_mean() {
+    let mut sum = SampleSum::new();
+    assert_eq!(sum.take(), 0);
+    for sample in [100, 200, 300, 401] {
+        sum.add(sample);
+    }
+    assert_eq!(sum.take(), 250);
+    assert_eq!(sum.take(), 0);
+}
+
+// The limiter runs once per block of 64 periods at 40kHz on the mean of the block. A current that
+// swings at the block rate has the same average wherever it is in the block.
+#[test]
+fn limits_the_mean_of_every_block() {
+    for phase in [0.0, 1.0, 2.0, 3.0] {
+        let mut limiter = CurrentLimiter::new();
+        limiter.set_limit(2000, 40000 / 64);
+        let mut sum = SampleSum::new();
+        let mut gain = 32768;
+        for n in 0..40000 * 4 {
+            let swing = (2.0 * std::f64::consts::PI * n as f64 / 64.0 + phase).sin();
+            let load = (1500.0 + 1000.0 * swing) as i32;
+            sum.add(sample(load * gain / 32768, 3300));
+            if n % 64 == 63 {
+                gain = limiter.process(current(sum.take(), 3300));
+            }
+        }
+        assert_eq!(gain, 32768);
+        assert!(
+            (limiter.average() - 1500).abs() <= 10,
+            "{} {}",
+            phase,
+            limiter.average()
+        );
+    }
+}
+
+#[test]
+fn fault_bits_are_distinct() {
+    let faults = [
+        Fault::Panic,
+        Fault::HardFault,
+        Fault::Overcurrent,
+        Fault::Stall,
+        Fault::Undervoltage,
+        Fault::Overtemperature,
+    ];
+    let all = faults.iter().fold(0, |all, fault| {
+        assert_eq!(all & fault.bit(), 0);
+        all | fault.bit()
+    });
+    assert_eq!(all.count_ones(), faults.len() as u32);
+}
+
+#[test]
+fn faults_are_counted_once() {
+    let mut faults = Faults::new();
+    assert!(!faults.any());
+
+    assert!(faults.set(Fault::Overtemperature, true));
+    assert!(!faults.set(Fault::Overtemperature, true));
+    assert!(faults.set(Fault::Undervoltage, true));
+    assert_eq!(
+        faults.bits(),
+        Fault::Overtemperature.bit() | Fault::Undervoltage.bit()
+    );
+    assert_eq!(faults.count(), 2);
+
+    assert!(faults.set(Fault::Overtemperature, false));
+    assert!(!faults.set(Fault::Overtemperature, false));
+    assert!(faults.set(Fault::Overtemperature, true));
+    assert_eq!(faults.count(), 3);
+
+    faults.set(Fault::Overtemperature, false);
+    faults.set(Fault::Undervoltage, false);
+    assert!(!faults.any());
+    assert_eq!(faults.count(), 3);
+}
@@ -0,0 +1,53 @@
+use parametric_speaker_core::dead_time::*;
+
+#[test]
+fn dtg_encoding() {
+    assert_eq!(ticks(0), 0);
+    assert_eq!(ticks(127), 127);
+    assert_eq!(ticks(0x80), 128);
+    assert_eq!(ticks(0xbf), 254);
+    assert_eq!(ticks(0xc0), 256);
+    assert_eq!(ticks(0xdf), 504);
+    assert_eq!(ticks(0xe0), 512);
+    assert_eq!(ticks(0xff), 1008);
+    // Every DTG value is a longer dead-time than the one before
+    let all: Vec<u32> = (0..=255).map(ticks).collect();
+    assert!(all.windows(2).all(|w| w[0] < w[1]));
+}
+
+// The shortest encoding that is at least as long as asked, and never below the minimum
+#[test]
+fn dtg_rounds_up() {
+    for clock in [16_000_000, 48_000_000, 96_000_000, 100_000_000] {
+        let longest = ticks(0xff) as u64 * 1_000_000_000;
+        for ns in 0..=12000 {
+            let dtg = dtg(ns, clock);
+            let wanted = ns.max(MIN_DEAD_TIME_NS) as u64 * clock as u64;
+            if wanted > longest {
+                assert_eq!(dtg, 0xff);
+                continue;
+            }
+            assert!(
+                ticks(dtg) as u64 * 1_000_000_000 >= wanted,
+                "{} {}",
+                ns,
+                clock
+            );
+            if let Some(shorter) = dtg.checked_sub(1) {
+                assert!(
+                    (ticks(shorter) as u64 * 1_000_000_000) < wanted,
+                    "{} {}",
+                    ns,
+                    clock
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn dead_time_in_ns() {
+    // 96MHz
+    assert_eq!(dead_time_ns(dtg(300, 96_000_000), 96_000_000), 302);
+    assert!(dead_time_ns(dtg(0, 96_000_000), 96_000_000) >= MIN_DEAD_TIME_NS);
+}
@@ -0,0 +1,37 @@
+use std::f64::consts::PI;
+
+use parametric_speaker_core::dynamics::*;
+
+// Level of the output in dBFS once the AGC has settled on a 1kHz sine of `amplitude`
+fn settled_level(amplitude: f64) -> f64 {
+    let mut dynamics = Dynamics::new();
+    dynamics.set_preset(Preset::Speech, 40000);
+    let mut sum = 0.0;
+    let mut count = 0;
+    for n in 0..400000 {
+        let x = (amplitude * (2.0 * PI * 1000.0 * n as f64 / 40000.0).sin()) as i16;
+        let y = dynamics.process(x) as f64;
+        if n > 360000 {
+            sum += y * y;
+            count += 1;
+        }
+    }
+    20.0 * ((sum / count as f64).sqrt() * 2f64.sqrt() / 32767.0).log10()
+}
+
+#[test]
+fn agc_evens_out_the_level() {
+    // -40dBFS and -6dBFS end up within 6dB
+    let quiet = settled_level(32767.0 * 0.01);
+    let loud = settled_level(32767.0 * 0.5);
+    assert!((quiet - loud).abs() < 6.0, "{} {}", quiet, loud);
+}
+
+#[test]
+fn off_passes_through() {
+    let mut dynamics = Dynamics::new();
+    dynamics.set_preset(Preset::Off, 40000);
+    for x in [0, 1234, -32768, 32767] {
+        assert_eq!(dynamics.process(x), x);
+    }
+}
@@ -0,0 +1,77 @@
+use std::f64::consts::PI;
+
+use parametric_speaker_core::eq::*;
+
+// Magnitude response in dB of [b0, b1, b2, a0, a1, a2] at 40kHz
+fn magnitude(c: &[f32; 6], frequency: f64) -> f64 {
+    let w = 2.0 * PI * frequency / 40000.0;
+    let c = c.map(|c| c as f64);
+    let response = |b: &[f64]| {
+        let re = b[0] + b[1] * w.cos() + b[2] * (2.0 * w).cos();
+        let im = -(b[1] * w.sin() + b[2] * (2.0 * w).sin());
+        (re * re + im * im).sqrt()
+    };
+    20.0 * (response(&c[..3]) / response(&c[3..])).log10()
+}
+
+fn band(kind: BandType, frequency: i32, gain: i32) -> Band {
+    Band {
+        kind,
+        frequency,
+        q: 71,
+        gain,
+    }
+}
+
+#[test]
+fn cookbook_coefficients() {
+    let c = coefficients(&band(BandType::Peaking, 1000, 60), 40000).unwrap();
+    assert!((magnitude(&c, 1000.0) - 6.0).abs() < 0.01);
+    assert!(magnitude(&c, 19000.0).abs() < 0.1);
+
+    let c = coefficients(&band(BandType::LowShelf, 200, -60), 40000).unwrap();
+    assert!((magnitude(&c, 10.0) + 6.0).abs() < 0.05);
+    assert!(magnitude(&c, 10000.0).abs() < 0.05);
+
+    let c = coefficients(&band(BandType::HighShelf, 5000, 90), 40000).unwrap();
+    assert!((magnitude(&c, 19900.0) - 9.0).abs() < 0.1);
+    assert!(magnitude(&c, 20.0).abs() < 0.05);
+
+    let c = coefficients(&band(BandType::HighPass, 100, 0), 40000).unwrap();
+    assert!((magnitude(&c, 100.0) + 3.0).abs() < 0.05);
+    assert!(magnitude(&c, 5000.0).abs() < 0.01);
+
+    let c = coefficients(&band(BandType::LowPass, 4000, 0), 40000).unwrap();
+    assert!((magnitude(&c, 4000.0) + 3.0).abs() < 0.05);
+    assert!(magnitude(&c, 50.0).abs() < 0.01);
+
+    assert!(coefficients(&Band::off(100), 40000).is_none());
+}
+
+#[test]
+fn fixed_point_peaking_band() {
+    let mut equalizer = Equalizer::new();
+    let mut bands = [Band::off(100); BANDS];
+    bands[1] = band(BandType::Peaking, 1000, 60);
+    equalizer.set_bands(&bands, 40000);
+    let mut peak = 0;
+    for n in 0..40000 {
+        let x = (8000.0 * (2.0 * PI * 1000.0 * n as f64 / 40000.0).sin()) as i16;
+        let y = equalizer.process(x);
+        if n > 20000 {
+            peak = peak.max(y as i32);
+        }
+    }
+    // +6dB
+    let expected = 8000.0 * 10f64.powf(0.3);
+    assert!((peak as f64 / expected - 1.0).abs() < 0.01, "{}", peak);
+}
+
+#[test]
+fn flat_without_bands() {
+    let mut equalizer = Equalizer::new();
+    equalizer.set_bands(&[Band::off(100); BANDS], 40000);
+    for x in [0, 1234, -32768, 32767] {
+        assert_eq!(equalizer.process(x), x);
+    }
+}
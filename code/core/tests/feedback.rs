@@ -0,0 +1,34 @@
+use parametric_speaker_core::feedback::*;
+use parametric_speaker_core::servo::{FillServo, Priming};
+
+#[test]
+fn nominal_feedback_is_10_14() {
+    assert_eq!(nominal_feedback(48000), 48 << 14);
+    assert_eq!(nominal_feedback(44100), (441 << 14) / 10);
+    assert_eq!(feedback(48000, 0), 48 << 14);
+}
+
+// The host sends at the rate asked for by the feedback, but its clock is 500ppm fast
+#[test]
+fn feedback_holds_the_fill_level() {
+    let mut servo = FillServo::new(2048);
+    let mut fill = 1000.0;
+    let mut value = feedback(48000, servo.update(1000));
+    for _ in 0..2_000_000 {
+        let sent = value as f64 / 16384.0 * 1.0005 * 40.0 / 48.0;
+        fill += sent - 40.0;
+        assert!(fill > 0.0 && fill < 4096.0);
+        value = feedback(48000, servo.update(fill as usize));
+    }
+    assert!((fill - 2048.0).abs() < 10.0, "{}", fill);
+}
+
+#[test]
+fn priming_waits_for_the_target() {
+    let mut priming = Priming::new(100);
+    assert!(!priming.is_primed(99));
+    assert!(priming.is_primed(100));
+    assert!(priming.is_primed(1));
+    priming.underrun();
+    assert!(!priming.is_primed(50));
+}
@@ -0,0 +1,34 @@
+use parametric_speaker_core::gain::*;
+
+#[test]
+fn volume_matches_db() {
+    for volume in (VOLUME_MIN..=VOLUME_MAX).step_by(37) {
+        let gain = volume_to_gain(volume, false) as f64 / 32768.0;
+        let expected = 10f64.powf(volume as f64 / 256.0 / 20.0);
+        let error = 20.0 * (gain / expected).log10();
+        assert!(error.abs() < 0.2, "{} {} {}", volume, gain, expected);
+    }
+    assert_eq!(volume_to_gain(0, true), 0);
+    // -∞ dB
+    assert_eq!(volume_to_gain(i16::MIN, false), 0);
+    assert_eq!(
+        volume_to_gain(i16::MIN + 1, false),
+        volume_to_gain(VOLUME_MIN, false)
+    );
+}
+
+#[test]
+fn smoothed_gain_reaches_the_target() {
+    let mut gain = SmoothedGain::new(UNITY_GAIN);
+    gain.set_target(0);
+    let mut y = 0;
+    for _ in 0..10000 {
+        y = gain.process(i16::MAX);
+    }
+    assert_eq!(y, 0);
+    gain.set_target(UNITY_GAIN);
+    for _ in 0..10000 {
+        y = gain.process(i16::MAX);
+    }
+    assert!(y >= i16::MAX - 1, "{}", y);
+}
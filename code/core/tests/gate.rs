@@ -0,0 +1,97 @@
+// The idle inputs are synthetic models of the noise floors of the sources, not recordings. Their
+// levels are taken from the datasheets and typical ground loops, and the generators are seeded so
+// that the tests are deterministic.
+
+use parametric_speaker_core::gate::*;
+
+const SAMPLE_RATE: u32 = 40000;
+
+// Deterministic white noise of ±1
+struct Noise(u32);
+
+impl Noise {
+    fn next(&mut self) -> f64 {
+        self.0 = self.0.wrapping_mul(1664525).wrapping_add(1013904223);
+        (self.0 >> 8) as f64 / (1 << 23) as f64 - 1.0
+    }
+}
+
+// Scales a model to an RMS level in dB relative to full scale
+fn scale(samples: impl Iterator<Item = f64>, level: f64) -> Vec<i16> {
+    let samples: Vec<f64> = samples.collect();
+    let rms = (samples.iter().map(|x| x * x).sum::<f64>() / samples.len() as f64).sqrt();
+    let gain = 10f64.powf(level / 20.0) * i16::MAX as f64 / rms;
+    samples.iter().map(|x| (x * gain).round() as i16).collect()
+}
+
+// Models the idle hiss of the analog input. The PCM1808 datasheet gives a dynamic range of 99dB,
+// which puts the floor of the converter itself near -99dBFS. The model is 24dB above that to leave
+// room for the line input stage in front of it, and pink as the hiss of op-amp inputs rises
+// towards low frequencies. Pink noise by Paul Kellet's economy filter.
+fn synthetic_hiss(seconds: u32) -> Vec<i16> {
+    let mut noise = Noise(12345);
+    let mut b = [0.0; 3];
+    let pink = (0..seconds * SAMPLE_RATE).map(|_| {
+        let white = noise.next();
+        b[0] = 0.99765 * b[0] + white * 0.0990460;
+        b[1] = 0.96300 * b[1] + white * 0.2965164;
+        b[2] = 0.57000 * b[2] + white * 1.0526913;
+        b[0] + b[1] + b[2] + white * 0.1848
+    });
+    scale(pink, -75.0)
+}
+
+// Models the mains hum of a ground loop on the analog input, 50Hz with its odd harmonics at
+// -70dBFS
+fn synthetic_hum(seconds: u32) -> Vec<i16> {
+    let hum = (0..seconds * SAMPLE_RATE).map(|n| {
+        let phase = 2.0 * core::f64::consts::PI * 50.0 * n as f64 / SAMPLE_RATE as f64;
+        phase.sin() + 0.3 * (3.0 * phase).sin() + 0.1 * (5.0 * phase).sin()
+    });
+    scale(hum, -70.0)
+}
+
+// Models the silence of a host that dithers its output, TPDF of ±1LSB
+fn synthetic_dither(seconds: u32) -> Vec<i16> {
+    let mut noise = Noise(54321);
+    (0..seconds * SAMPLE_RATE)
+        .map(|_| (noise.next() * 0.5 + noise.next() * 0.5 + 0.5).floor() as i16)
+        .collect()
+}
+
+#[test]
+fn stays_closed_on_synthetic_idle_inputs() {
+    for idle in [synthetic_hiss(2), synthetic_hum(2), synthetic_dither(2)] {
+        let mut gate = Gate::new();
+        gate.set_settings(&GateSettings::DEFAULT, SAMPLE_RATE);
+        for x in idle {
+            gate.process(x);
+            assert!(!gate.is_open());
+        }
+    }
+}
+
+#[test]
+fn opens_on_audio_over_synthetic_hiss_and_closes_after_the_hold_time() {
+    let mut gate = Gate::new();
+    gate.set_settings(&GateSettings::DEFAULT, SAMPLE_RATE);
+    let hiss = synthetic_hiss(2);
+    let mut hiss = hiss.iter().copied();
+
+    // -40dBFS over the hiss
+    let opened = (0..4000).position(|n| {
+        gate.process((327.0 * (n as f64 * 0.157).sin()) as i16 + hiss.next().unwrap());
+        gate.is_open()
+    });
+    assert!(opened.unwrap() < 500);
+
+    // Holds for 500ms
+    let closed = (0..40000).position(|_| {
+        gate.process(hiss.next().unwrap());
+        !gate.is_open()
+    });
+    let closed = closed.unwrap();
+    assert!(closed > 20000 && closed < 23000, "{}", closed);
+    // The carrier ramps over the 20ms fade time
+    assert_eq!(gate.fade(), 800);
+}
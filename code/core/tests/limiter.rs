@@ -0,0 +1,52 @@
+use std::f64::consts::PI;
+
+use parametric_speaker_core::limiter::*;
+
+#[test]
+fn db_conversion() {
+    assert_eq!(gain_from_db(0), 32768);
+    assert!(
+        (gain_from_db(-60) - 16423).abs() <= 2,
+        "{}",
+        gain_from_db(-60)
+    );
+    assert_eq!(gain_to_db(32768), 0);
+    assert_eq!(gain_to_db(gain_from_db(-120)), -120);
+}
+
+// A 12dB burst is caught by the look-ahead with hardly any clipping
+#[test]
+fn peaks_stay_below_the_threshold() {
+    let mut limiter = Limiter::new();
+    limiter.set_parameters(-60, 200, 50, 40000);
+    let threshold = (gain_from_db(-60) * 32767) >> 15;
+    let mut clipped = 0;
+    for n in 0..40000 {
+        let amplitude = if (8000..8400).contains(&n) {
+            32000.0
+        } else {
+            8000.0
+        };
+        let x = (amplitude * (2.0 * PI * 1000.0 * n as f64 / 40000.0).sin()) as i16;
+        let y = (limiter.process(x) as i32).abs();
+        assert!(y <= threshold);
+        if y == threshold {
+            clipped += 1;
+        }
+    }
+    assert!(clipped <= 10, "{}", clipped);
+    // Released again
+    assert_eq!(gain_to_db(limiter.gain()), 0);
+}
+
+#[test]
+fn quiet_audio_is_only_delayed() {
+    let mut limiter = Limiter::new();
+    limiter.set_parameters(0, 200, 50, 40000);
+    for n in 0..1000 {
+        let y = limiter.process((n * 7) as i16) as i32;
+        if n >= LOOKAHEAD as i32 {
+            assert_eq!(y, (n - LOOKAHEAD as i32) * 7);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::f64::consts::PI;
+
+use parametric_speaker_core::gain::UNITY_GAIN;
+use parametric_speaker_core::hilbert::{self, Hilbert};
+use parametric_speaker_core::modulation::*;
+
+fn cosine(amplitude: f64, frequency: f64, n: i32) -> f64 {
+    amplitude * (2.0 * PI * frequency * n as f64 / 40000.0).cos()
+}
+
+#[test]
+fn isqrt_rounds_down() {
+    for x in [0, 1, 2, 3, 4, 15, 16, 17, 12345678, 1 << 30, u32::MAX] {
+        let root = isqrt(x) as u64;
+        assert!(
+            root * root <= x as u64 && (root + 1) * (root + 1) > x as u64,
+            "{}",
+            x
+        );
+    }
+}
+
+#[test]
+fn dsb_and_sram_envelopes() {
+    for percent in [0, 50, 80, 100] {
+        let index = index_from_percent(percent) as f64 / 32768.0;
+        for (modulation, root) in [(Modulation::Dsb, false), (Modulation::Sram, true)] {
+            let mut modulator = Modulator::new(modulation, index_from_percent(percent));
+            for x in (i16::MIN..=i16::MAX).step_by(7) {
+                let dsb = (1.0 + index * x as f64 / 32768.0) / 2.0;
+                let expected = if root { dsb.sqrt() } else { dsb } * 32768.0;
+                let envelope = modulator.modulate(x);
+                assert_eq!(envelope.phase, 0);
+                let amplitude = envelope.amplitude as f64;
+                assert!(
+                    (amplitude - expected).abs() <= 2.0,
+                    "{:?} {} {} {} {}",
+                    modulation,
+                    percent,
+                    x,
+                    amplitude,
+                    expected
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn hilbert_shifts_by_90_degrees() {
+    for frequency in [600.0, 1000.0, 5000.0, 15000.0] {
+        let mut hilbert = Hilbert::new();
+        let mut error: f64 = 0.0;
+        for n in 0..4000 {
+            let (delayed, shifted) = hilbert.process(cosine(20000.0, frequency, n).round() as i16);
+            if n > 200 {
+                let t = n - hilbert::DELAY as i32;
+                assert!((delayed as f64 - cosine(20000.0, frequency, t)).abs() <= 1.0);
+                let expected = 20000.0 * (2.0 * PI * frequency * t as f64 / 40000.0).sin();
+                error = error.max((shifted as f64 - expected).abs());
+            }
+        }
+        // 1%
+        assert!(error < 200.0, "{} {}", frequency, error);
+    }
+}
+
+#[test]
+fn ssb_envelope_stays_in_range() {
+    for modulation in [Modulation::UpperSsb, Modulation::LowerSsb] {
+        let mut modulator = Modulator::new(modulation, UNITY_GAIN);
+        for n in 0..2000 {
+            let envelope = modulator.modulate(cosine(16000.0, 1000.0, n) as i16);
+            assert!((0..=32768).contains(&envelope.amplitude));
+        }
+    }
+}
+
+#[test]
+fn phase_shifter_moves_the_carrier() {
+    // A quarter turn of a 2400 tick period is 600 ticks more in total
+    let mut shifter = PhaseShifter::new(2400);
+    let total: u32 = (0..10).map(|_| shifter.update(i32::MIN / 2)).sum();
+    assert_eq!(total, 24000 + 600);
+    // Without a phase change the period stays the same
+    let total: u32 = (0..10).map(|_| shifter.update(i32::MIN / 2)).sum();
+    assert_eq!(total, 24000);
+}
@@ -0,0 +1,18 @@
+use parametric_speaker_core::pcm1808::*;
+
+#[test]
+fn sign_extends_24_bits() {
+    assert_eq!(channel_to_i32(0x7fff, 0xff00), 0x7f_ffff);
+    assert_eq!(channel_to_i32(0x8000, 0x0000), -0x80_0000);
+    assert_eq!(channel_to_i32(0xffff, 0xff00), -1);
+    // The lowest byte is not part of the sample
+    assert_eq!(channel_to_i32(0x0000, 0x01ff), 1);
+}
+
+#[test]
+fn downmixes_to_16_bits() {
+    assert_eq!(downmix(0x7f_ffff, 0x7f_ffff), i16::MAX);
+    assert_eq!(downmix(-0x80_0000, -0x80_0000), i16::MIN);
+    assert_eq!(downmix(0x7f_ffff, -0x80_0000), -1);
+    assert_eq!(frame_to_i16(&[0x1234, 0x5600, 0x1234, 0x5600]), 0x1234);
+}
@@ -0,0 +1,124 @@
+use parametric_speaker_core::dynamics::{Dynamics, Preset};
+use parametric_speaker_core::gain::{SmoothedGain, UNITY_GAIN};
+use parametric_speaker_core::gate::{Gate, GateSettings};
+use parametric_speaker_core::limiter::Limiter;
+use parametric_speaker_core::pipeline::*;
+
+struct Add(i32);
+
+impl Processor for Add {
+    fn process(&mut self, block: &mut [i32]) {
+        for x in block {
+            *x += self.0;
+        }
+    }
+}
+
+struct Multiply(i32);
+
+impl Processor for Multiply {
+    fn process(&mut self, block: &mut [i32]) {
+        for x in block {
+            *x *= self.0;
+        }
+    }
+}
+
+#[test]
+fn chain_runs_in_order() {
+    let mut block = [0, 1, 2];
+    Add(1).then(Multiply(3)).process(&mut block);
+    assert_eq!(block, [3, 6, 9]);
+    Multiply(3).then(Add(1)).process(&mut block);
+    assert_eq!(block, [10, 19, 28]);
+}
+
+#[test]
+fn chain_of_borrowed_processors() {
+    let mut add = Add(2);
+    let mut multiply = Multiply(2);
+    let mut block = [1, 2];
+    let mut pipeline = (&mut multiply).then(&mut add);
+    pipeline.process(&mut block);
+    pipeline.first().0 = 10;
+    pipeline.process(&mut block);
+    assert_eq!(block, [42, 62]);
+    assert_eq!(multiply.0, 10);
+}
+
+#[test]
+fn left_out_processors_pass_through() {
+    let mut block = [1, 2];
+    Add(1).then(None::<Multiply>).process(&mut block);
+    assert_eq!(block, [2, 3]);
+    Add(1).then(Some(Multiply(2))).process(&mut block);
+    assert_eq!(block, [6, 8]);
+}
+
+#[test]
+fn samples_are_saturated() {
+    assert_eq!(saturate(100_000), i16::MAX);
+    assert_eq!(saturate(-100_000), i16::MIN);
+    assert_eq!(saturate(-5), -5);
+
+    let mut gain = SmoothedGain::new(UNITY_GAIN);
+    let mut block = [100_000, -100_000, 5];
+    Processor::process(&mut gain, &mut block);
+    assert!((32000..=32767).contains(&block[0]), "{:?}", block);
+    assert!((-32768..=-32000).contains(&block[1]), "{:?}", block);
+}
+
+#[test]
+fn block_matches_sample_processing() {
+    let mut by_sample = Limiter::new();
+    let mut by_block = Limiter::new();
+    by_sample.set_parameters(-60, 200, 50, 40000);
+    by_block.set_parameters(-60, 200, 50, 40000);
+    let input: Vec<i16> = (0..1000)
+        .map(|n| ((n * 977) % 65536 - 32768) as i16)
+        .collect();
+    let expected: Vec<i32> = input.iter().map(|&x| by_sample.process(x) as i32).collect();
+    let mut block: Vec<i32> = input.iter().map(|&x| x as i32).collect();
+    for chunk in block.chunks_mut(64) {
+        Processor::process(&mut by_block, chunk);
+    }
+    assert_eq!(block, expected);
+}
+
+// The pipeline of a source: the gate follows the source before the dynamics and the gain
+#[test]
+fn source_pipeline_matches_sample_processing() {
+    let mut gate = Gate::new();
+    let mut dynamics = Dynamics::new();
+    let mut gain = SmoothedGain::new(UNITY_GAIN);
+    gate.set_settings(&GateSettings::DEFAULT, 40000);
+    dynamics.set_preset(Preset::Speech, 40000);
+    gain.set_target(UNITY_GAIN / 2);
+    let input: Vec<i16> = (0..4000)
+        .map(|n| ((n * 977) % 65536 - 32768) as i16 / (1 + n as i16 / 400))
+        .collect();
+    let expected: Vec<i32> = input
+        .iter()
+        .map(|&x| {
+            gate.process(x);
+            gain.process(dynamics.process(x)) as i32
+        })
+        .collect();
+    let open = gate.is_open();
+
+    let mut gate = Gate::new();
+    let mut dynamics = Dynamics::new();
+    let mut gain = SmoothedGain::new(UNITY_GAIN);
+    gate.set_settings(&GateSettings::DEFAULT, 40000);
+    dynamics.set_preset(Preset::Speech, 40000);
+    gain.set_target(UNITY_GAIN / 2);
+    let mut block: Vec<i32> = input.iter().map(|&x| x as i32).collect();
+    for chunk in block.chunks_mut(64) {
+        (&mut gate)
+            .then(Some(&mut dynamics))
+            .then(&mut gain)
+            .process(chunk);
+    }
+    assert_eq!(block, expected);
+    assert_eq!(gate.is_open(), open);
+}
@@ -0,0 +1,154 @@
+use parametric_speaker_core::resample::*;
+use parametric_speaker_core::servo::FillServo;
+
+// Cubic Lagrange polynomial through x[0..4] at -1, 0, 1 and 2, evaluated at `mu`
+fn lagrange(x: &[i16; 4], mu: f64) -> f64 {
+    let x = x.map(|x| x as f64);
+    -mu * (mu - 1.0) * (mu - 2.0) / 6.0 * x[0] + (mu + 1.0) * (mu - 1.0) * (mu - 2.0) / 2.0 * x[1]
+        - (mu + 1.0) * mu * (mu - 2.0) / 2.0 * x[2]
+        + (mu + 1.0) * mu * (mu - 1.0) / 6.0 * x[3]
+}
+
+// A deterministic test signal that covers most of the range
+fn input(n: u32) -> i16 {
+    (n.wrapping_mul(2654435761) >> 16) as i16
+}
+
+#[test]
+fn interpolation_is_cubic_lagrange() {
+    let x = [100, -2000, 3000, 7000];
+    assert_eq!(interpolate(&x, 0), -2000);
+    assert_eq!(interpolate(&x, 1 << 15), 3000);
+    for mu in (0..1 << 15).step_by(1000) {
+        let expected = lagrange(&x, mu as f64 / 32768.0);
+        let y = interpolate(&x, mu) as f64;
+        assert!((y - expected).abs() <= 1.0, "{} {} {}", mu, y, expected);
+    }
+}
+
+// Reference values of the integer implementation, which must not change between targets
+#[test]
+fn interpolation_is_bit_exact() {
+    let x = [100, -2000, 3000, 7000];
+    let y = [0, 8192, 16384, 24576].map(|mu| interpolate(&x, mu));
+    assert_eq!(y, [-2000, -1099, 119, 1527]);
+}
+
+// Resamples the start of the test signal from `rate` to the carrier rate
+fn resample(rate: u32) -> Vec<i16> {
+    let mut resampler = Resampler::new(rate, 40000);
+    let mut output = Vec::new();
+    for n in 0..32 {
+        resampler.process(input(n), |y| output.push(y));
+    }
+    output
+}
+
+// Reference output of the whole resampler, which must not change between targets either
+#[test]
+fn resampler_is_bit_exact() {
+    assert_eq!(
+        resample(44100),
+        [
+            0, 423, -6056, -13474, 5683, 11263, 19129, -15855, 15120, -871, -27926, 9532, -4944,
+            23008, -12485, -803, 5130, -29530, 2874, -16038, 23397, -6840, -17118, 5842, 7457,
+            19330, -16184, 11610, -398, -29968,
+        ]
+    );
+    assert_eq!(
+        resample(48000),
+        [
+            0, 801, -12081, -208, -5604, 30940, -1194, -3447, 10031, -26824, 11817, -6163, 21471,
+            -16957, 10675, -7304, -25286, -1322, 7959, 5708, -26428, 10118, 4720, 18198, -18658,
+            19984, -12151,
+        ]
+    );
+}
+
+#[test]
+fn converts_the_rate() {
+    for rate in USB_SAMPLE_RATES {
+        let mut resampler = Resampler::new(rate, 40000);
+        let mut count = 0;
+        for n in 0..rate {
+            resampler.process((n % 100) as i16, |_| count += 1);
+        }
+        assert!((count - 40000i32).abs() <= 1, "{} {}", rate, count);
+    }
+}
+
+// The producer runs `ppm` faster than the consumer. The servo has to keep the queue from
+// running empty or full.
+#[test]
+fn servo_follows_clock_drift() {
+    const QUEUE_SIZE: i64 = 4096;
+    for ppm in [-500.0, 0.0, 500.0] {
+        let mut resampler = Resampler::new(40000, 40000);
+        let mut servo = FillServo::new(QUEUE_SIZE as usize / 2);
+        let mut fill = QUEUE_SIZE / 2;
+        // In samples of the consumer
+        let mut time = 0.0f64;
+        let mut consumed = 0i64;
+        let block = 64.0 / (1.0 + ppm * 1e-6);
+        // 10 minutes
+        for _ in 0..40000 * 600 / 64 {
+            let mut produced = 0;
+            for n in 0..64 {
+                resampler.process(n as i16, |_| produced += 1);
+            }
+            time += block;
+            let drained = time as i64 - consumed;
+            consumed = time as i64;
+            fill += produced - drained;
+            assert!(fill > 0 && fill < QUEUE_SIZE, "{} {}", ppm, fill);
+            resampler.set_correction(servo.update(fill as usize));
+        }
+        assert!((fill - QUEUE_SIZE / 2).abs() < 64, "{} {}", ppm, fill);
+    }
+}
+
+// The same over hours of playback. The resampler is reduced to its phase accumulator, which
+// produces the same amount of samples per block as long as the step does not change within it.
+#[test]
+fn servo_never_drifts() {
+    const QUEUE_SIZE: i64 = 4096;
+    const ONE: i64 = 1 << 32;
+    for ppm in [-500.0, -100.0, 0.0, 100.0, 500.0] {
+        let mut resampler = Resampler::new(40000, 40000);
+        let mut servo = FillServo::new(QUEUE_SIZE as usize / 2);
+        let mut fill = QUEUE_SIZE / 2;
+        let mut time = 0.0f64;
+        let mut consumed = 0i64;
+        let block = 64.0 / (1.0 + ppm * 1e-6);
+        // Position of the next output sample from the start of the block and input samples per
+        // output sample, Q32
+        let mut phase = 0;
+        let mut step = ONE;
+        let (mut min, mut max) = (fill, fill);
+        // 4 hours
+        for n in 0..40000 * 3600 * 4 / 64 {
+            // The outputs fall at phase, phase + step and so on up to the end of the block
+            let produced = (64 * ONE - phase + step - 1) / step;
+            phase += produced * step - 64 * ONE;
+            // The model is checked against the resampler for the first minute
+            if n < 40000 * 60 / 64 {
+                let mut expected = 0;
+                for n in 0..64 {
+                    resampler.process(n as i16, |_| expected += 1);
+                }
+                assert_eq!(produced, expected);
+            }
+            time += block;
+            let drained = time as i64 - consumed;
+            consumed = time as i64;
+            fill += produced - drained;
+            min = min.min(fill);
+            max = max.max(fill);
+            let correction = servo.update(fill as usize);
+            step = ONE - ((ONE * correction) >> 32);
+            resampler.set_correction(correction);
+        }
+        assert!(min > 0 && max < QUEUE_SIZE, "{} {} {}", ppm, min, max);
+        assert!((fill - QUEUE_SIZE / 2).abs() < 64, "{} {}", ppm, fill);
+    }
+}
@@ -0,0 +1,53 @@
+use parametric_speaker_core::reset::*;
+
+#[test]
+fn reason_from_flags() {
+    let none = ResetFlags::default();
+    let power_on = ResetFlags {
+        power_on: true,
+        brownout: true,
+        ..none
+    };
+    let brownout = ResetFlags {
+        brownout: true,
+        ..none
+    };
+    let watchdog = ResetFlags {
+        watchdog: true,
+        ..none
+    };
+    let software = ResetFlags {
+        software: true,
+        ..none
+    };
+    // A record left over from before the power was lost is ignored
+    let panic = Some(ResetReason::Panic);
+    assert_eq!(reset_reason(power_on, panic), ResetReason::PowerOn);
+    assert_eq!(reset_reason(brownout, panic), ResetReason::Brownout);
+    let stalled = Some(ResetReason::AudioStalled);
+    assert_eq!(reset_reason(watchdog, stalled), ResetReason::AudioStalled);
+    assert_eq!(reset_reason(watchdog, None), ResetReason::Watchdog);
+    assert_eq!(reset_reason(software, None), ResetReason::Software);
+    assert_eq!(reset_reason(none, None), ResetReason::Pin);
+}
+
+#[test]
+fn record_is_taken_once() {
+    // Random contents after a power-on
+    let mut memory = [0xdead_beef_u32, 7];
+    let record = unsafe { &mut *(memory.as_mut_ptr() as *mut ResetRecord) };
+    assert!(!record.is_set());
+    assert_eq!(record.take(), None);
+    record.set(ResetReason::UsbStalled);
+    assert!(record.is_set());
+    assert_eq!(record.take(), Some(ResetReason::UsbStalled));
+    assert_eq!(record.take(), None);
+}
+
+#[test]
+fn reason_ids() {
+    for id in 0..9 {
+        assert_eq!(ResetReason::try_from(id).unwrap() as u32, id);
+    }
+    assert!(ResetReason::try_from(9).is_err());
+}
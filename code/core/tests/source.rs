@@ -0,0 +1,80 @@
+use parametric_speaker_core::source::*;
+
+const USB: SourceStatus = SourceStatus {
+    usb_configured: true,
+    usb_streaming: true,
+    jack_plugged: true,
+};
+const ANALOG: SourceStatus = SourceStatus {
+    usb_configured: true,
+    usb_streaming: false,
+    jack_plugged: true,
+};
+const NONE: SourceStatus = SourceStatus {
+    usb_configured: false,
+    usb_streaming: false,
+    jack_plugged: false,
+};
+
+#[test]
+fn usb_takes_precedence_in_auto_mode() {
+    assert_eq!(select(SourceMode::Auto, USB), Some(Source::Usb));
+    assert_eq!(select(SourceMode::Auto, ANALOG), Some(Source::Analog));
+    assert_eq!(select(SourceMode::Auto, NONE), None);
+    assert_eq!(select(SourceMode::ForceUsb, NONE), Some(Source::Usb));
+    assert_eq!(select(SourceMode::ForceAnalog, USB), Some(Source::Analog));
+}
+
+#[test]
+fn arbiter_crossfades() {
+    let mut arbiter = Arbiter::new(SourceMode::Auto);
+    assert!(arbiter.update(USB));
+    assert!(!arbiter.update(USB));
+    assert_eq!(arbiter.active(), Some(Source::Usb));
+
+    // Half way through the crossfade
+    let mut mixed = 0;
+    for _ in 0..CROSSFADE_SAMPLES / 2 {
+        mixed = arbiter.mix(1000, -1000);
+    }
+    assert!((mixed - 500).abs() <= 10, "{}", mixed);
+    for _ in 0..CROSSFADE_SAMPLES {
+        mixed = arbiter.mix(1000, -1000);
+    }
+    assert_eq!(mixed, 1000);
+    assert!(!arbiter.is_audible(Source::Analog));
+
+    assert!(arbiter.update(ANALOG));
+    arbiter.mix(1000, -1000);
+    assert!(arbiter.is_audible(Source::Usb));
+    for _ in 0..2 * CROSSFADE_SAMPLES {
+        mixed = arbiter.mix(1000, -1000);
+    }
+    assert_eq!(mixed, -1000);
+    assert!(!arbiter.is_audible(Source::Usb));
+}
+
+#[test]
+fn stream_times_out() {
+    let mut monitor = StreamMonitor::new();
+    assert!(!monitor.update(0));
+    assert!(monitor.update(1));
+    for _ in 1..STREAM_TIMEOUT_SAMPLES {
+        assert!(monitor.update(1));
+    }
+    assert!(!monitor.update(1));
+}
+
+#[test]
+fn jack_is_debounced() {
+    let mut debouncer = Debouncer::new(false);
+    for _ in 1..JACK_DEBOUNCE_SAMPLES {
+        assert!(!debouncer.update(true));
+    }
+    // A bounce restarts the count
+    assert!(!debouncer.update(false));
+    for _ in 1..JACK_DEBOUNCE_SAMPLES {
+        assert!(!debouncer.update(true));
+    }
+    assert!(debouncer.update(true));
+}
@@ -0,0 +1,77 @@
+use parametric_speaker_core::carrier::level;
+use parametric_speaker_core::thermal::*;
+
+#[test]
+fn steinhart_hart_fits_b3950() {
+    let close = |resistance: f32, expected: f32, tolerance: f32| {
+        let temperature = steinhart_hart(resistance);
+        assert!(
+            (temperature - expected).abs() < tolerance,
+            "{} {}",
+            resistance,
+            temperature
+        );
+    };
+    close(32_650.0, 0.0, 0.2);
+    close(10_000.0, 25.0, 0.2);
+    close(3_588.0, 50.0, 1.5);
+    close(678.0, 100.0, 0.2);
+}
+
+#[test]
+fn ntc_divider() {
+    assert!((ntc_resistance(2048) - 10_000.0).abs() < 10.0);
+    assert!((ntc_temperature(2048).unwrap() - 25.0).abs() < 0.3);
+    // Open and shorted
+    assert!(ntc_temperature(4095).is_none());
+    assert!(ntc_temperature(0).is_none());
+}
+
+#[test]
+fn internal_sensor_calibration() {
+    let (cal30, cal110) = (943, 1209);
+    let voltage = |sample: u32| sample * 3300 / 4095;
+    assert!((internal_temperature(voltage(943), cal30, cal110) - 30.0).abs() < 0.5);
+    assert!((internal_temperature(voltage(1209), cal30, cal110) - 110.0).abs() < 0.5);
+}
+
+#[test]
+fn derating_is_linear() {
+    assert_eq!(derating(600, 700, 950), 32768);
+    assert_eq!(derating(700, 700, 950), 32768);
+    assert!((derating(825, 700, 950) - 20480).abs() < 2);
+    assert_eq!(derating(950, 700, 950), 8192);
+    assert_eq!(derating(2000, 700, 950), 8192);
+}
+
+#[test]
+fn trips_with_hysteresis() {
+    let mut monitor = ThermalMonitor::new();
+    monitor.set_limits(700, 950, 100);
+    assert!(!monitor.update(250));
+    assert_eq!(monitor.temperature(), 250);
+
+    let mut settle = |temperature| {
+        let mut tripped = false;
+        for _ in 0..2000 {
+            tripped = monitor.update(temperature);
+        }
+        tripped
+    };
+    assert!(settle(1000));
+    // Within the hysteresis
+    assert!(settle(900));
+    assert!(!settle(840));
+    assert!(monitor.gain() > 8192 && monitor.gain() < 32768);
+}
+
+#[test]
+fn heat_derates_the_carrier() {
+    let mut monitor = ThermalMonitor::new();
+    monitor.set_limits(700, 950, 100);
+    monitor.update(825);
+    assert_eq!(monitor.gain(), derating(825, 700, 950));
+    assert_eq!(level(32768, &[monitor.gain()]), monitor.gain());
+    // The battery and the heat derate together
+    assert_eq!(level(32768, &[16384, monitor.gain()]), monitor.gain() / 2);
+}